@@ -1,10 +1,16 @@
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, FromArgMatches};
 use colored::*;
-use ffzap_core::{load_paths, CmdArgs, Logger, Processor, Progress};
+use ffzap_core::args::Command;
+use ffzap_core::{
+    concat, load_paths, watcher, CmdArgs, Config, Logger, Outcome, Processor, Progress,
+};
+use std::ffi::OsString;
+use std::process::exit;
 use std::sync::Arc;
 
 fn main() {
-    let cmd_args = CmdArgs::parse();
+    let cmd_args = parse_args();
 
     if cmd_args.eta {
         let line_1 = "Warning: ETA is a highly experimental feature and prone to absurd estimations. If your encoding process has long pauses in-between each processed file, you WILL experience incredibly inaccurate estimations!"
@@ -17,24 +23,61 @@ fn main() {
         println!("{line_2}");
     }
 
-    let paths = load_paths(&cmd_args);
-    let progress = Arc::new(Progress::new(paths.len(), cmd_args.eta));
+    let (mut paths, skipped_paths) = match &cmd_args.command {
+        Some(Command::Watch(_)) => (vec![], vec![]),
+        Some(Command::Thumbs(_) | Command::Concat(_) | Command::Extract(_)) | None => {
+            load_paths(&cmd_args)
+        }
+    };
+
+    // concat processes groups of files instead of single files
+    let groups = match &cmd_args.command {
+        Some(Command::Concat(concat_args)) => {
+            match concat::group(std::mem::take(&mut paths), concat_args.regex.as_deref()) {
+                Ok((groups, unmatched)) => {
+                    for path in &unmatched {
+                        println!("{} doesn't match --regex, ignoring it.", path.display());
+                    }
+                    groups
+                }
+                Err(err) => {
+                    eprintln!("{}", format!("Invalid regex: {err}").bright_red());
+                    exit(1);
+                }
+            }
+        }
+        _ => vec![],
+    };
+    let total = match &cmd_args.command {
+        Some(Command::Concat(_)) => groups.len(),
+        _ => paths.len(),
+    };
+    let progress = Arc::new(Progress::new(total, cmd_args.eta));
     // IDE may throw an error here that only 1 out of 2 arguments are supplied, but that's just the IDE not understanding
     // that ffzap-shared is used without the ui feature and therefore process_files only accepts 2 arguments.
     let logger = Arc::new(Logger::new(Arc::clone(&progress)));
     let processor = Processor::new(Arc::clone(&logger), Arc::clone(&progress));
 
-    // IDE may throw an error here that only 7 out of 8 arguments are supplied, but that's just the IDE not understanding
-    // that ffzap-shared is used without the ui feature and therefore process_files only accepts 7 arguments.
-    processor.process_files(
-        paths,
-        cmd_args.thread_count,
-        cmd_args.ffmpeg_options,
-        cmd_args.output,
-        cmd_args.overwrite,
-        cmd_args.verbose,
-        cmd_args.delete,
-    );
+    match &cmd_args.command {
+        Some(Command::Watch(watch_args)) => {
+            if let Err(err) = watcher::watch(
+                &processor,
+                &cmd_args,
+                watch_args,
+                Arc::clone(&logger),
+                Arc::clone(&progress),
+            ) {
+                eprintln!("{}", err.bright_red());
+                exit(1);
+            }
+        }
+        Some(Command::Concat(concat_args)) => {
+            processor.concat_groups(groups, &cmd_args, concat_args)
+        }
+        Some(Command::Thumbs(_) | Command::Extract(_)) | None => {
+            processor.process_files(paths, &cmd_args)
+        }
+    }
 
     let processed = progress.value();
     let total = progress.len();
@@ -51,6 +94,52 @@ fn main() {
     }
     println!("{final_output}");
 
+    let up_to_date_paths = processor.get_up_to_date_paths();
+    if !up_to_date_paths.is_empty() {
+        println!(
+            "{} of them were already up to date and have been skipped.",
+            up_to_date_paths.len()
+        );
+    }
+
+    let report = processor.get_report();
+    let skipped = report.count(Outcome::Skipped);
+    if skipped > 0 && cmd_args.skip_if.is_some() {
+        println!("{skipped} of them matched --skip-if and have been skipped.");
+    }
+    // concat also copies streams, but that's expected of it
+    let remuxed = report.count(Outcome::Remuxed);
+    if remuxed > 0 && cmd_args.remux_if.is_some() {
+        println!("{remuxed} of them matched --remux-if and have been remuxed.");
+    }
+    let not_beneficial = report.count(Outcome::NotBeneficial);
+    if not_beneficial > 0 {
+        println!("{not_beneficial} of them didn't get smaller, their sources have been kept.");
+    }
+
+    if let Some(report_path) = &cmd_args.report {
+        match report.write(report_path) {
+            Ok(_) => println!("A report has been written to {}", report_path.display()),
+            Err(err) => eprintln!(
+                "{}",
+                format!("Could not write report to {}: {err}", report_path.display()).bright_red()
+            ),
+        }
+    }
+
+    logger.append_skipped_paths_to_log(&skipped_paths);
+
+    if cmd_args.verbose && !skipped_paths.is_empty() {
+        let headline = "The following files were skipped because they didn't match the filter:"
+            .bright_yellow()
+            .to_string();
+        println!("\n{}", headline);
+        for path in skipped_paths.iter() {
+            let path = path.bright_yellow().to_string();
+            println!("{path}");
+        }
+    }
+
     let failed_paths = processor.get_failed_paths();
     logger.append_failed_paths_to_log(&std::sync::Mutex::new(failed_paths.clone()).lock().unwrap());
 
@@ -65,3 +154,60 @@ fn main() {
         }
     }
 }
+
+/// Parses the command line. If `--preset` is passed, the values of the preset become the
+/// defaults, so flags on the command line still override them.
+fn parse_args() -> CmdArgs {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let mut command = CmdArgs::command();
+
+    if let Some(name) = preset_name(&args) {
+        let preset = Config::load().and_then(|config| config.preset(&name).cloned());
+        match preset {
+            Ok(preset) => command = preset.apply_defaults(command),
+            Err(err) => {
+                eprintln!("{}", err.bright_red());
+                exit(1);
+            }
+        }
+    }
+
+    let matches = command
+        .try_get_matches_from_mut(args)
+        .unwrap_or_else(|err| err.exit());
+    let cmd_args = CmdArgs::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+
+    // subcommands make all arguments optional, but thumbs, concat and extract need inputs like a normal run
+    let has_inputs =
+        cmd_args.input.is_some() || cmd_args.file_list.is_some() || cmd_args.manifest.is_some();
+    if matches!(
+        cmd_args.command,
+        Some(Command::Thumbs(_) | Command::Concat(_) | Command::Extract(_))
+    ) && !has_inputs
+    {
+        command
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "this subcommand needs files to process, pass --input, --file-list or --manifest before it",
+            )
+            .exit();
+    }
+
+    cmd_args
+}
+
+/// Finds the value of `--preset` before the arguments are parsed for real
+fn preset_name(args: &[OsString]) -> Option<String> {
+    let mut args = args.iter().map(|arg| arg.to_string_lossy());
+
+    while let Some(arg) = args.next() {
+        if arg == "--preset" {
+            return args.next().map(|name| name.to_string());
+        }
+        if let Some(name) = arg.strip_prefix("--preset=") {
+            return Some(name.to_string());
+        }
+    }
+
+    None
+}
@@ -1,29 +1,70 @@
-use clap::Parser;
+use crate::extract::StreamKind;
+use crate::load::ThreadCount;
+use crate::sanitize::Sanitize;
+use clap::{Args, Parser, Subcommand};
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 
 #[derive(Parser, Debug, Clone, Deserialize, Serialize)]
-#[command(version, about)]
+#[command(version, about, subcommand_negates_reqs = true)]
+#[command(group(clap::ArgGroup::new("quality_threshold").args(["min_ssim", "min_psnr"]).multiple(true)))]
 pub struct CmdArgs {
-    /// The amount of threads you want to utilize. most systems can handle 2. Go higher if you have a powerful computer. Default is 2. Can't be lower than 1
-    #[arg(short, long, default_value_t = 2, value_parser = clap::value_parser!(u16).range(1..))]
-    pub thread_count: u16,
+    #[command(subcommand)]
+    #[serde(skip)]
+    pub command: Option<Command>,
+
+    /// Apply a named preset from the config file (e.g. ~/.config/ffzap/config.toml on Linux). Options passed on the command line override the values of the preset
+    #[arg(long)]
+    pub preset: Option<String>,
+
+    /// The amount of threads you want to utilize. most systems can handle 2. Go higher if you have a powerful computer. Default is 2. Can't be lower than 1. Use auto to process more or fewer files at once depending on CPU usage, load average and available memory
+    #[arg(short, long, visible_alias = "threads", default_value_t = ThreadCount::Fixed(2), value_parser = ThreadCount::parse)]
+    pub thread_count: ThreadCount,
+
+    /// With --thread-count auto, the fewest files processed at once. Default is 1
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    pub min_threads: Option<u16>,
+
+    /// With --thread-count auto, the most files processed at once. Default is the number of CPU cores
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    pub max_threads: Option<u16>,
 
     /// Options you want to pass to ffmpeg. For the output file name, use --output
     #[arg(short, long, allow_hyphen_values = true)]
     pub ffmpeg_options: Option<String>,
 
     /// The files you want to process.
-    #[arg(short, long, num_args = 1.., required_unless_present = "file_list", conflicts_with = "file_list")]
-    pub input: Option<Vec<String>>,
+    #[arg(short, long, num_args = 1.., required_unless_present_any = ["file_list", "manifest"], conflicts_with_all = ["file_list", "manifest"])]
+    pub input: Option<Vec<PathBuf>>,
+
+    /// Path to a file containing paths to process. One path per line, blank lines and lines starting with # are ignored. Use - to read the list from stdin
+    #[arg(long, required_unless_present_any = ["input", "manifest"], conflicts_with_all = ["input", "manifest"])]
+    pub file_list: Option<PathBuf>,
 
-    /// Path to a file containing paths to process. One path per line
-    #[arg(long, required_unless_present = "input", conflicts_with = "input")]
-    pub file_list: Option<String>,
+    /// Path to a manifest with one row per file and optional ffmpeg options and output per file, replacing --ffmpeg-options and --output for that file. Either CSV with the columns input, ffmpeg_options and output, or JSON lines like {"input": "a.mkv", "ffmpeg_options": "-crf 20"}. Use - to read it from stdin
+    #[arg(long, required_unless_present_any = ["input", "file_list"], conflicts_with_all = ["input", "file_list"])]
+    pub manifest: Option<PathBuf>,
+
+    /// The directory {{reldir}} is relative to. Defaults to the directory that was passed to --input. Files outside of it keep their full path without the leading root
+    #[arg(long)]
+    pub base_dir: Option<PathBuf>,
+
+    /// Paths in the file list are separated by NUL characters instead of newlines, e.g. the output of find -print0
+    #[arg(long, default_value_t = false, requires = "file_list")]
+    pub null: bool,
 
     /// If ffmpeg should overwrite files if they already exist. Default is false
     #[arg(long, default_value_t = false)]
     pub overwrite: bool,
 
+    /// Skip files whose output already exists and is newer than the input, like make does. Outdated outputs are re-created
+    #[arg(long, default_value_t = false)]
+    pub skip_existing_newer: bool,
+
+    /// Stricter version of --skip-existing-newer: skip files whose output was created from the exact same input contents and ffmpeg options in a previous run
+    #[arg(long, default_value_t = false)]
+    pub skip_unchanged: bool,
+
     /// If verbose logs should be shown while ffzap is running
     #[arg(long, default_value_t = false)]
     pub verbose: bool,
@@ -32,6 +73,93 @@ pub struct CmdArgs {
     #[arg(long, default_value_t = false)]
     pub delete: bool,
 
+    /// Only process files whose ffprobe output matches this expression. Combine conditions with &&, || and !, e.g. "has_video && vcodec != hevc && duration > 60".
+    ///
+    /// Fields: duration (seconds), width, height, bitrate, vcodec, acodec, format, has_video, has_audio, has_subtitle
+    #[arg(long, value_parser = crate::filter::validate)]
+    pub filter: Option<String>,
+
+    /// Skip files that already match these conditions, e.g. "vcodec=hevc,acodec=aac". Conditions are separated by commas and use the fields of --filter
+    #[arg(long, value_parser = crate::filter::validate_conditions)]
+    pub skip_if: Option<String>,
+
+    /// Copy the streams into the output instead of re-encoding them if the file matches these conditions, e.g. "vcodec=hevc,acodec=aac". Only applies to the main output, outputs of --extra-output are always encoded
+    #[arg(long, value_parser = crate::filter::validate_conditions)]
+    pub remux_if: Option<String>,
+
+    /// Write what happened to each file (transcoded, remuxed, skipped, up to date, not beneficial or failed) to this file as JSON
+    #[arg(long)]
+    pub report: Option<PathBuf>,
+
+    /// Move the source file into this directory after it was successfully processed. If the process fails, the file is kept where it is.
+    #[arg(long, conflicts_with = "delete")]
+    pub move_processed: Option<PathBuf>,
+
+    /// Replace the extension used for {{ext}} in the output pattern, e.g. "avi=mkv,wmv=mp4" to put everything into containers that fit the new codecs. Other extensions are kept
+    #[arg(long, value_parser = crate::template::validate_map)]
+    pub ext_map: Option<String>,
+
+    /// Encode every file in two passes, e.g. for bitrate targeted encodes with -b:v. The first pass only analyzes the file, its log files are removed afterwards
+    #[arg(long, default_value_t = false)]
+    pub two_pass: bool,
+
+    /// Make every output fit into this many bytes, e.g. 25M. The video bitrate is calculated from the duration of the file and the audio bitrate, and the file is encoded in two passes. Files that end up too large are encoded again with a lower bitrate
+    #[arg(long, value_parser = crate::target_size::parse)]
+    pub target_size: Option<u64>,
+
+    /// Normalize the loudness of every file to these EBU R128 targets, e.g. I=-16:TP=-1.5:LRA=11. Each file is measured first and then corrected linearly while it's encoded. Replaces any -af of the ffmpeg options
+    #[arg(long, value_parser = crate::loudnorm::validate)]
+    pub loudnorm: Option<String>,
+
+    /// Make output file and directory names valid on the given file system. Invalid characters are replaced with _, names that are too long are shortened and a warning is logged for every changed path
+    #[arg(long, value_enum)]
+    pub sanitize: Option<Sanitize>,
+
+    /// Create another output from the same input, with its own ffmpeg options and output pattern, e.g. --extra-output "-vn -c:a aac" "audio/{{name}}.m4a". Can be used multiple times. All outputs are created by a single ffmpeg run and a file only succeeds if all of them do
+    #[arg(long, num_args = 2, value_names = ["FFMPEG_OPTIONS", "OUTPUT"], allow_hyphen_values = true, action = clap::ArgAction::Append)]
+    #[serde(default)]
+    pub extra_output: Vec<String>,
+
+    /// Compare the video of the main output against its source with ffmpeg's ssim and psnr filters after it's encoded. The scores are written to the log and to the --report. Outputs of --extra-output aren't compared
+    #[arg(long, default_value_t = false)]
+    pub quality_check: bool,
+
+    /// Fail files whose SSIM is below this value from 0 to 1, e.g. 0.95. Implies --quality-check. The outputs of failed files are removed
+    #[arg(long, value_parser = crate::quality::parse_ssim)]
+    pub min_ssim: Option<f64>,
+
+    /// Fail files whose PSNR in dB is below this value, e.g. 40. Implies --quality-check. The outputs of failed files are removed
+    #[arg(long, value_parser = crate::quality::parse_psnr)]
+    pub min_psnr: Option<f64>,
+
+    /// Instead of failing files below --min-ssim or --min-psnr, encode them again with the ffmpeg options of this preset from the config file. Files that are still below fail. The fallback is a single plain encode, so it can't be combined with --two-pass, --target-size or --segment-length
+    #[arg(long, requires = "quality_threshold", conflicts_with_all = ["two_pass", "target_size", "segment_length"])]
+    pub quality_fallback: Option<String>,
+
+    /// Discard outputs that aren't smaller than their source by at least this many percent (0 if no value is given). The source is kept, --delete and --move-processed leave it alone and the report marks the file as not beneficial. Only the main output is compared
+    #[arg(long, num_args = 0..=1, default_missing_value = "0", value_name = "MARGIN", value_parser = crate::processor::parse_margin)]
+    pub keep_smaller: Option<f64>,
+
+    /// With --keep-smaller, copy the source to the path of a discarded output, with the extension of the source
+    #[arg(long, default_value_t = false, requires = "keep_smaller")]
+    pub copy_original: bool,
+
+    /// Give every output the access and modification times of its source after it was successfully processed
+    #[arg(long, default_value_t = false)]
+    pub preserve_times: bool,
+
+    /// Copy the extended attributes (e.g. tags and comments) of the source to every output. Unix only
+    #[arg(long, default_value_t = false)]
+    pub preserve_xattrs: bool,
+
+    /// Give every output the owner and group of its source. Unix only, usually requires root
+    #[arg(long, default_value_t = false)]
+    pub preserve_owner: bool,
+
+    /// Split every file at keyframes into chunks of about this many seconds, encode the chunks on all threads in parallel and join them losslessly. Meant for a few long files, which are processed one after another. Audio and subtitles are encoded in one piece when the chunks are joined. Can't be combined with --extra-output, --two-pass, --target-size, -map or -filter_complex
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..), conflicts_with_all = ["extra_output", "two_pass", "target_size"])]
+    pub segment_length: Option<u64>,
+
     /// Displays the current eta in the progressbar
     #[arg(long, default_value_t = false)]
     pub eta: bool,
@@ -40,13 +168,93 @@ pub struct CmdArgs {
     ///
     /// {{dir}}  - Entire specified file path, e.g. ./path/to/file.txt -> ?./path/to/
     ///
+    /// {{reldir}} - Path of the file's directory relative to the directory passed to --input (or --base-dir), e.g. for -i /mnt/media: /mnt/media/shows/a.mkv -> shows
+    ///
     /// {{name}} - Original file's name (without extension)
     ///
     /// {{ext}}  - Original file's extension
     ///
+    /// {{parent}} - Name of the file's parent directory
+    ///
+    /// {{width}}, {{height}}, {{vcodec}}, {{acodec}}, {{duration}}, {{title}} - Read from the file with ffprobe. Other tags are available as {{tag:<name>}}, e.g. {{tag:artist}}
+    ///
+    /// {{mtime:<format>}} - The file's modification time, e.g. {{mtime:%Y-%m-%d}}
+    ///
+    /// {{date:<format>}} - The time the job was started
+    ///
+    /// {{index:<width>}} - Position of the file in the job, zero padded to the given width or the length of the total count
+    ///
+    /// {{frame:<width>}} - Number of the image created by ffzap thumbs, zero padded like {{index}}. Empty when transcoding
+    ///
+    /// {{stream_index}}, {{lang}} and {{codec}} - Index, language (und if unknown) and codec of the stream written by ffzap extract. Empty when transcoding
+    ///
+    /// Filters can be appended to any placeholder: {{name|lower}}, {{name|upper}}, {{name|slug}}, {{name|replace:" ":"_"}}, {{name|truncate:40}}, {{ext|map:avi=mkv,wmv=mp4}} and {{title|default:name}} (or {{title|default:"untitled"}})
+    ///
     /// Example: /destination/{{dir}}/{{name}}_transcoded.{{ext}}
     ///
     /// Outputs the file in /destination, mirroring the original structure and keeping both the file extension and name, while adding _transcoded to the name.
-    #[arg(short, long)]
+    #[arg(short, long, value_parser = crate::template::validate)]
     pub output: String,
 }
+
+#[derive(Subcommand, Debug, Clone, Deserialize, Serialize)]
+pub enum Command {
+    /// Keep running and process new files as soon as they appear in a directory. Options for processing them are passed before the subcommand, e.g. ffzap -f "-c:v libx265" -o "out/{{name}}.mp4" watch incoming/
+    Watch(WatchArgs),
+
+    /// Create preview images instead of transcoding: evenly spaced frames or a contact sheet of every input. Inputs and the output pattern are passed before the subcommand, e.g. ffzap -i videos/ -o "thumbs/{{name}}_{{frame}}.jpg" thumbs --count 6. --ffmpeg-options are added to the options of every image
+    Thumbs(ThumbsArgs),
+
+    /// Join the files of each group into one output: all files of a directory, or with --regex all files of a directory whose names share a match, e.g. chapters of action cameras. Streams are copied if all files of a group have the same codec parameters, otherwise they're re-encoded with --ffmpeg-options. Inputs and the output pattern are passed before the subcommand, placeholders refer to the first file of a group, e.g. ffzap -i DCIM/ -o "Joined/{{name}}.mp4" concat --regex "G[OP](?:PR|\d\d)(\d{4})\.MP4$"
+    Concat(ConcatArgs),
+
+    /// Write every stream of the given kinds to a file of its own, in a container that fits its codec. Inputs and the output pattern are passed before the subcommand, e.g. ffzap -i movies/ -o "subs/{{name}}.{{stream_index}}.{{lang}}.{{ext}}" extract --streams subtitle. {{ext}} is the extension of the container of the stream. --ffmpeg-options are added to the options of every stream
+    Extract(ExtractArgs),
+}
+
+#[derive(Args, Debug, Clone, Deserialize, Serialize)]
+pub struct WatchArgs {
+    /// The directory to watch. Subdirectories are watched as well
+    pub dir: PathBuf,
+
+    /// How many seconds the size of a new file has to stay the same before it's considered complete. Default is 5
+    #[arg(long, default_value_t = 5)]
+    pub settle_time: u64,
+
+    /// Also process the files that are already in the directory when ffzap starts
+    #[arg(long, default_value_t = false)]
+    pub process_existing: bool,
+}
+
+#[derive(Args, Debug, Clone, Deserialize, Serialize)]
+pub struct ThumbsArgs {
+    /// How many evenly spaced frames to extract from every input. Use {{frame}} in the output pattern to number them. Default is 4
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..))]
+    pub count: u32,
+
+    /// Create a single contact sheet per input with this many columns and rows instead, e.g. 4x3
+    #[arg(long, value_parser = crate::thumbs::validate_grid, conflicts_with = "count")]
+    pub sheet: Option<String>,
+
+    /// Scale every frame to this width in pixels, keeping the aspect ratio
+    #[arg(long)]
+    pub width: Option<u32>,
+}
+
+#[derive(Args, Debug, Clone, Deserialize, Serialize)]
+pub struct ConcatArgs {
+    /// Group files whose names match this regex by the first capture group (or the whole match), instead of joining all files of a directory. Files that don't match are left alone
+    #[arg(long, value_parser = crate::concat::validate_regex)]
+    pub regex: Option<String>,
+
+    /// Always re-encode with --ffmpeg-options, even if the streams could be copied
+    #[arg(long, default_value_t = false)]
+    pub reencode: bool,
+}
+
+#[derive(Args, Debug, Clone, Deserialize, Serialize)]
+pub struct ExtractArgs {
+    /// The kinds of streams to extract, separated by commas, e.g. subtitle,audio
+    #[arg(long, value_enum, value_delimiter = ',', required = true)]
+    pub streams: Vec<StreamKind>,
+}
@@ -0,0 +1,208 @@
+use crate::{InputFile, MediaInfo};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs::write;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Groups `files` for `ffzap concat`. Without `regex`, all files of a directory form a group.
+/// With it, files of a directory are grouped by the first capture group of the match, or the
+/// whole match if there is no capture group. Groups and the files in them are sorted by path.
+///
+/// Returns the groups and the files whose names don't match `regex`.
+pub fn group(
+    files: Vec<InputFile>,
+    regex: Option<&str>,
+) -> Result<(Vec<Vec<InputFile>>, Vec<PathBuf>), String> {
+    let regex = regex
+        .map(Regex::new)
+        .transpose()
+        .map_err(|err| err.to_string())?;
+    let mut groups: BTreeMap<(PathBuf, String), Vec<InputFile>> = BTreeMap::new();
+    let mut unmatched = vec![];
+
+    for file in files {
+        let dir = file.path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let key = match &regex {
+            Some(regex) => {
+                let name = file.path.file_name().unwrap_or_default().to_string_lossy();
+                match regex.captures(&name) {
+                    Some(captures) => captures
+                        .get(1)
+                        .or_else(|| captures.get(0))
+                        .map(|key| key.as_str().to_string())
+                        .unwrap_or_default(),
+                    None => {
+                        unmatched.push(file.path);
+                        continue;
+                    }
+                }
+            }
+            None => String::new(),
+        };
+
+        groups.entry((dir, key)).or_default().push(file);
+    }
+
+    let groups = groups
+        .into_values()
+        .enumerate()
+        .map(|(i, mut files)| {
+            files.sort_by(|a, b| a.path.cmp(&b.path));
+            // the first file names the output, so it gets the position of the group
+            files[0].index = i + 1;
+            files
+        })
+        .collect();
+
+    Ok((groups, unmatched))
+}
+
+/// Checks that the streams of all files have the same parameters, which the concat demuxer
+/// needs to join them without re-encoding. Returns what differs otherwise.
+pub fn check_compatible(files: &[PathBuf], infos: &[MediaInfo]) -> Result<(), String> {
+    let Some(first) = infos.first() else {
+        return Ok(());
+    };
+    let signature = |info: &MediaInfo| {
+        info.streams
+            .iter()
+            .map(|stream| {
+                format!(
+                    "{} {} {} {}x{} {} {}fps {} {} {}ch",
+                    stream.codec_type.as_deref().unwrap_or("unknown"),
+                    stream.codec_name.as_deref().unwrap_or("unknown"),
+                    stream.profile.as_deref().unwrap_or_default(),
+                    stream.width.unwrap_or_default(),
+                    stream.height.unwrap_or_default(),
+                    stream.pix_fmt.as_deref().unwrap_or_default(),
+                    stream.r_frame_rate.as_deref().unwrap_or_default(),
+                    stream.time_base.as_deref().unwrap_or_default(),
+                    stream.sample_rate.as_deref().unwrap_or_default(),
+                    stream.channels.unwrap_or_default(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let expected = signature(first);
+    for (file, info) in files.iter().zip(infos).skip(1) {
+        let actual = signature(info);
+        if actual != expected {
+            return Err(format!(
+                "{} has the streams [{}] instead of [{}]",
+                file.display(),
+                actual.join(", "),
+                expected.join(", ")
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the list of `files` for the concat demuxer to `list`
+pub fn write_list(files: &[PathBuf], list: &Path) -> io::Result<()> {
+    let lines = files
+        .iter()
+        .map(|file| {
+            // the list is relative to its own directory otherwise
+            let file = std::path::absolute(file).unwrap_or_else(|_| file.clone());
+            format!("file '{}'\n", file.to_string_lossy().replace('\'', r"'\''"))
+        })
+        .collect::<String>();
+
+    write(list, lines)
+}
+
+/// Joins the files in `list` by copying all of their streams
+pub fn copy_command(list: &Path, output: &Path) -> Command {
+    let mut command = Command::new("ffmpeg");
+    command
+        .args(["-f", "concat", "-safe", "0", "-i"])
+        .arg(list)
+        .args(["-map", "0", "-c", "copy"])
+        .arg(output);
+
+    command
+}
+
+/// Joins `files` with the concat filter, which re-encodes them. Video is scaled and padded to
+/// the size and frame rate of the first file, audio is resampled to its sample rate and
+/// channel layout. Streams are only joined if every file has them.
+pub fn reencode_command(
+    files: &[PathBuf],
+    infos: &[MediaInfo],
+    ffmpeg_options: &[&str],
+    output: &Path,
+) -> Result<Command, String> {
+    let first = infos.first().ok_or("There are no files to join")?;
+    let video = first
+        .first_stream("video")
+        .filter(|_| infos.iter().all(|info| info.has_stream("video")));
+    let audio = first
+        .first_stream("audio")
+        .filter(|_| infos.iter().all(|info| info.has_stream("audio")));
+
+    let mut filters = vec![];
+    let mut concat_inputs = String::new();
+    for i in 0..files.len() {
+        if let Some(video) = video {
+            let (width, height) = match (video.width, video.height) {
+                (Some(width), Some(height)) => (width, height),
+                _ => return Err("The size of the first video is unknown".to_string()),
+            };
+            let frame_rate = video.r_frame_rate.as_deref().unwrap_or("30");
+            filters.push(format!(
+                "[{i}:v:0]scale={width}:{height}:force_original_aspect_ratio=decrease,pad={width}:{height}:(ow-iw)/2:(oh-ih)/2,setsar=1,fps={frame_rate}[v{i}]"
+            ));
+            concat_inputs.push_str(&format!("[v{i}]"));
+        }
+        if let Some(audio) = audio {
+            let sample_rate = audio.sample_rate.as_deref().unwrap_or("48000");
+            let channel_layout = audio.channel_layout.as_deref().unwrap_or("stereo");
+            filters.push(format!(
+                "[{i}:a:0]aresample={sample_rate},aformat=channel_layouts={channel_layout}[a{i}]"
+            ));
+            concat_inputs.push_str(&format!("[a{i}]"));
+        }
+    }
+
+    let mut outputs = vec![];
+    if video.is_some() {
+        outputs.push("[v]");
+    }
+    if audio.is_some() {
+        outputs.push("[a]");
+    }
+    if outputs.is_empty() {
+        return Err("The files have no video or audio in common".to_string());
+    }
+    filters.push(format!(
+        "{concat_inputs}concat=n={}:v={}:a={}{}",
+        files.len(),
+        video.is_some() as u8,
+        audio.is_some() as u8,
+        outputs.concat()
+    ));
+
+    let mut command = Command::new("ffmpeg");
+    for file in files {
+        command.arg("-i").arg(file);
+    }
+    command.arg("-filter_complex").arg(filters.join(";"));
+    for stream in outputs {
+        command.args(["-map", stream]);
+    }
+    command.args(ffmpeg_options).arg(output);
+
+    Ok(command)
+}
+
+/// Checks `--regex` for use as a clap value parser.
+pub fn validate_regex(value: &str) -> Result<String, String> {
+    Regex::new(value)
+        .map(|_| value.to_string())
+        .map_err(|err| err.to_string())
+}
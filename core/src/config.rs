@@ -0,0 +1,112 @@
+use crate::load::ThreadCount;
+use clap::Command;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// The contents of `config.toml`. Presets are defined as tables, e.g.
+///
+/// ```toml
+/// [presets.hevc-archive]
+/// ffmpeg_options = "-c:v libx265 -crf 24 -preset slow -c:a copy"
+/// output = "archive/{{name}}.mkv"
+/// thread_count = 2
+/// filter = "vcodec != hevc"
+/// ```
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct Config {
+    #[serde(default)]
+    pub presets: BTreeMap<String, Preset>,
+}
+
+/// Values a preset can provide. Everything that isn't set is left to the command line.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Preset {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ffmpeg_options: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread_count: Option<ThreadCount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+}
+
+impl Config {
+    pub fn path() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("ffzap")
+            .join("config.toml")
+    }
+
+    /// Loads the config file. A missing file is the same as an empty config.
+    pub fn load() -> Result<Config, String> {
+        let path = Self::path();
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents)
+                .map_err(|err| format!("Could not parse {}: {err}", path.display())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(format!("Could not read {}: {err}", path.display())),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = Self::path();
+        let contents = toml::to_string_pretty(self)
+            .map_err(|err| format!("Could not serialize config: {err}"))?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|err| format!("Could not create {}: {err}", parent.display()))?;
+        }
+
+        fs::write(&path, contents)
+            .map_err(|err| format!("Could not write {}: {err}", path.display()))
+    }
+
+    pub fn preset(&self, name: &str) -> Result<&Preset, String> {
+        self.presets.get(name).ok_or_else(|| {
+            let available = self.presets.keys().cloned().collect::<Vec<_>>();
+            match available.is_empty() {
+                true => format!(
+                    "Preset '{name}' not found. No presets are defined in {}",
+                    Self::path().display()
+                ),
+                false => format!(
+                    "Preset '{name}' not found. Available presets are: {}",
+                    available.join(", ")
+                ),
+            }
+        })
+    }
+}
+
+impl Preset {
+    /// Uses the values of the preset as defaults of `command`, so flags passed on the command
+    /// line still take precedence.
+    pub fn apply_defaults(&self, mut command: Command) -> Command {
+        let values = [
+            ("ffmpeg_options", self.ffmpeg_options.clone()),
+            ("output", self.output.clone()),
+            (
+                "thread_count",
+                self.thread_count.map(|count| count.to_string()),
+            ),
+            ("filter", self.filter.clone()),
+        ];
+
+        for (id, value) in values {
+            if let Some(value) = value {
+                // --output is required unless a preset provides it
+                command = command.mut_arg(id, |arg| arg.default_value(value).required(false));
+            }
+        }
+
+        command
+    }
+}
@@ -0,0 +1,69 @@
+use crate::args::ExtractArgs;
+use crate::probe::StreamInfo;
+use crate::MediaInfo;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Kinds of streams `ffzap extract` can pull out of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamKind {
+    Video,
+    Audio,
+    Subtitle,
+}
+
+impl StreamKind {
+    fn codec_type(self) -> &'static str {
+        match self {
+            StreamKind::Video => "video",
+            StreamKind::Audio => "audio",
+            StreamKind::Subtitle => "subtitle",
+        }
+    }
+}
+
+/// The index of every stream of `info` that `args` asks for, with the ffmpeg options that
+/// write it to a file of its own.
+pub fn stream_options(args: &ExtractArgs, info: &MediaInfo) -> Vec<(u32, String)> {
+    info.streams
+        .iter()
+        .filter(|stream| {
+            args.streams
+                .iter()
+                .any(|kind| stream.codec_type.as_deref() == Some(kind.codec_type()))
+        })
+        .map(|stream| {
+            let (_, codec) = container(stream);
+            (stream.index, format!("-map 0:{} -c {codec}", stream.index))
+        })
+        .collect()
+}
+
+/// The extension of the container a stream is written to, and the codec it's written with.
+/// Streams are copied unless the container can't hold their codec.
+pub fn container(stream: &StreamInfo) -> (&'static str, &'static str) {
+    match stream.codec_name.as_deref().unwrap_or_default() {
+        "aac" | "alac" => ("m4a", "copy"),
+        "mp3" => ("mp3", "copy"),
+        "opus" => ("opus", "copy"),
+        "vorbis" => ("ogg", "copy"),
+        "flac" => ("flac", "copy"),
+        "ac3" => ("ac3", "copy"),
+        "eac3" => ("eac3", "copy"),
+        "dts" => ("dts", "copy"),
+        "truehd" => ("thd", "copy"),
+        "pcm_s16le" | "pcm_s24le" | "pcm_s32le" | "pcm_f32le" => ("wav", "copy"),
+        "subrip" => ("srt", "copy"),
+        "ass" | "ssa" => ("ass", "copy"),
+        "webvtt" => ("vtt", "copy"),
+        // mp4 subtitles only exist in mp4 files
+        "mov_text" => ("srt", "srt"),
+        "hdmv_pgs_subtitle" => ("sup", "copy"),
+        _ => match stream.codec_type.as_deref() {
+            Some("audio") => ("mka", "copy"),
+            Some("subtitle") => ("mks", "copy"),
+            _ => ("mkv", "copy"),
+        },
+    }
+}
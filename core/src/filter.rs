@@ -0,0 +1,606 @@
+use crate::probe::MediaInfo;
+use std::fmt;
+
+/// A filter expression that is evaluated against ffprobe output, e.g.
+/// `has_video && vcodec != hevc && duration > 60`.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    expr: Expr,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Has(StreamKind),
+    Compare(Field, Op, Literal),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum StreamKind {
+    Video,
+    Audio,
+    Subtitle,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Duration,
+    Width,
+    Height,
+    Bitrate,
+    VideoCodec,
+    AudioCodec,
+    Format,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone)]
+enum Literal {
+    Number(f64),
+    Text(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+impl Filter {
+    pub fn parse(source: &str) -> Result<Filter, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+
+        if let Some(token) = parser.peek() {
+            return Err(format!("Unexpected {} in filter expression", token));
+        }
+
+        Ok(Filter { expr })
+    }
+
+    /// Parses a comma separated list of conditions that all have to match, e.g.
+    /// `vcodec=hevc,acodec=aac`. Commas in quoted values don't separate conditions.
+    pub fn parse_conditions(conditions: &str) -> Result<Filter, String> {
+        Self::parse(&split_conditions(conditions).join(" && "))
+    }
+
+    /// Returns `Ok` if the file matches, otherwise the reason why it doesn't.
+    pub fn evaluate(&self, info: &MediaInfo) -> Result<(), String> {
+        self.expr.evaluate(info)
+    }
+}
+
+/// Checks a filter expression for use as a clap value parser.
+pub fn validate(source: &str) -> Result<String, String> {
+    Filter::parse(source).map(|_| source.to_string())
+}
+
+/// Checks a list of conditions for use as a clap value parser.
+pub fn validate_conditions(conditions: &str) -> Result<String, String> {
+    Filter::parse_conditions(conditions).map(|_| conditions.to_string())
+}
+
+impl Expr {
+    fn evaluate(&self, info: &MediaInfo) -> Result<(), String> {
+        match self {
+            Expr::Or(left, right) => match (left.evaluate(info), right.evaluate(info)) {
+                (Err(left), Err(right)) => Err(format!("{left} and {right}")),
+                _ => Ok(()),
+            },
+            Expr::And(left, right) => {
+                left.evaluate(info)?;
+                right.evaluate(info)
+            }
+            Expr::Not(inner) => match inner.evaluate(info) {
+                Ok(_) => Err(format!("matches {inner}")),
+                Err(_) => Ok(()),
+            },
+            Expr::Has(kind) => {
+                if info.has_stream(kind.codec_type()) {
+                    Ok(())
+                } else {
+                    Err(format!("has no {} stream", kind.codec_type()))
+                }
+            }
+            Expr::Compare(field, op, literal) => {
+                // a file without the field has no value that could be equal
+                let actual = match (field.value(info), op) {
+                    (Some(actual), _) => actual,
+                    (None, Op::Ne) => return Ok(()),
+                    (None, _) => return Err(format!("{field} is unknown")),
+                };
+
+                let matches = match (&actual, literal) {
+                    (Literal::Number(actual), Literal::Number(expected)) => {
+                        op.holds(actual.partial_cmp(expected))
+                    }
+                    (Literal::Text(actual), Literal::Text(expected)) => {
+                        op.holds(Some(actual.to_lowercase().cmp(&expected.to_lowercase())))
+                    }
+                    _ => false,
+                };
+
+                if matches {
+                    Ok(())
+                } else {
+                    Err(format!("{field} is {actual}"))
+                }
+            }
+        }
+    }
+}
+
+impl StreamKind {
+    fn codec_type(&self) -> &'static str {
+        match self {
+            StreamKind::Video => "video",
+            StreamKind::Audio => "audio",
+            StreamKind::Subtitle => "subtitle",
+        }
+    }
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Field> {
+        match name {
+            "duration" => Some(Field::Duration),
+            "width" => Some(Field::Width),
+            "height" => Some(Field::Height),
+            "bitrate" => Some(Field::Bitrate),
+            "vcodec" => Some(Field::VideoCodec),
+            "acodec" => Some(Field::AudioCodec),
+            "format" => Some(Field::Format),
+            _ => None,
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            Field::Duration | Field::Width | Field::Height | Field::Bitrate
+        )
+    }
+
+    fn value(&self, info: &MediaInfo) -> Option<Literal> {
+        let video = info.first_stream("video");
+        let audio = info.first_stream("audio");
+
+        match self {
+            Field::Duration => info.duration().map(Literal::Number),
+            Field::Width => video?.width.map(|w| Literal::Number(w as f64)),
+            Field::Height => video?.height.map(|h| Literal::Number(h as f64)),
+            Field::Bitrate => info.bit_rate().map(|b| Literal::Number(b as f64)),
+            Field::VideoCodec => video?.codec_name.clone().map(Literal::Text),
+            Field::AudioCodec => audio?.codec_name.clone().map(Literal::Text),
+            Field::Format => info.format.format_name.clone().map(Literal::Text),
+        }
+    }
+}
+
+impl Op {
+    fn from_token(token: &str) -> Option<Op> {
+        match token {
+            "==" | "=" => Some(Op::Eq),
+            "!=" => Some(Op::Ne),
+            "<" => Some(Op::Lt),
+            "<=" => Some(Op::Le),
+            ">" => Some(Op::Gt),
+            ">=" => Some(Op::Ge),
+            _ => None,
+        }
+    }
+
+    fn holds(&self, ordering: Option<std::cmp::Ordering>) -> bool {
+        use std::cmp::Ordering::*;
+
+        match (self, ordering) {
+            (_, None) => false,
+            (Op::Eq, Some(o)) => o == Equal,
+            (Op::Ne, Some(o)) => o != Equal,
+            (Op::Lt, Some(o)) => o == Less,
+            (Op::Le, Some(o)) => o != Greater,
+            (Op::Gt, Some(o)) => o == Greater,
+            (Op::Ge, Some(o)) => o != Less,
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Or(left, right) => write!(f, "({left} || {right})"),
+            Expr::And(left, right) => write!(f, "({left} && {right})"),
+            Expr::Not(inner) => write!(f, "!{inner}"),
+            Expr::Has(kind) => write!(f, "has_{}", kind.codec_type()),
+            Expr::Compare(field, op, literal) => write!(f, "{field} {op} {literal}"),
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Field::Duration => "duration",
+            Field::Width => "width",
+            Field::Height => "height",
+            Field::Bitrate => "bitrate",
+            Field::VideoCodec => "vcodec",
+            Field::AudioCodec => "acodec",
+            Field::Format => "format",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+        };
+        write!(f, "{op}")
+    }
+}
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Literal::Number(n) => write!(f, "{n}"),
+            Literal::Text(t) => write!(f, "{t}"),
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "'{word}'"),
+            Token::Op(op) => write!(f, "'{op}'"),
+            Token::LParen => write!(f, "'('"),
+            Token::RParen => write!(f, "')'"),
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_and()?;
+        while self.peek() == Some(&Token::Op("||")) {
+            self.pos += 1;
+            let right = self.parse_and()?;
+            left = Expr::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_unary()?;
+        while self.peek() == Some(&Token::Op("&&")) {
+            self.pos += 1;
+            let right = self.parse_unary()?;
+            left = Expr::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Op("!")) => Ok(Expr::Not(Box::new(self.parse_unary()?))),
+            Some(Token::LParen) => {
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => Err("Missing ')' in filter expression".to_string()),
+                }
+            }
+            Some(Token::Word(word)) => self.parse_condition(word),
+            Some(token) => Err(format!("Unexpected {} in filter expression", token)),
+            None => Err("Unexpected end of filter expression".to_string()),
+        }
+    }
+
+    fn parse_condition(&mut self, word: String) -> Result<Expr, String> {
+        match word.as_str() {
+            "has_video" => return Ok(Expr::Has(StreamKind::Video)),
+            "has_audio" => return Ok(Expr::Has(StreamKind::Audio)),
+            "has_subtitle" => return Ok(Expr::Has(StreamKind::Subtitle)),
+            _ => {}
+        }
+
+        let field = Field::from_name(&word).ok_or_else(|| {
+            format!("Unknown filter field '{word}'. Available fields are: duration, width, height, bitrate, vcodec, acodec, format, has_video, has_audio, has_subtitle")
+        })?;
+
+        let op = match self.next() {
+            Some(Token::Op(op)) => Op::from_token(op)
+                .ok_or_else(|| format!("Expected a comparison after '{field}'"))?,
+            _ => return Err(format!("Expected a comparison after '{field}'")),
+        };
+
+        let value = match self.next() {
+            Some(Token::Word(value)) => value,
+            _ => return Err(format!("Expected a value after '{field} {op}'")),
+        };
+
+        let literal = if field.is_numeric() {
+            Literal::Number(
+                parse_number(&value)
+                    .ok_or_else(|| format!("'{value}' is not a valid number for '{field}'"))?,
+            )
+        } else if matches!(op, Op::Eq | Op::Ne) {
+            Literal::Text(value)
+        } else {
+            return Err(format!("'{field}' can only be compared with == or !="));
+        };
+
+        Ok(Expr::Compare(field, op, literal))
+    }
+}
+
+/// Splits `conditions` at every comma that isn't inside quotes
+fn split_conditions(conditions: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut quote = None;
+
+    for (i, c) in conditions.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            (',', None) => {
+                parts.push(&conditions[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&conditions[start..]);
+
+    parts
+}
+
+/// Parses numbers with an optional k, M or G suffix, e.g. `2.5M`
+pub(crate) fn parse_number(value: &str) -> Option<f64> {
+    let (number, factor) = match value.chars().last()? {
+        'k' | 'K' => (&value[..value.len() - 1], 1e3),
+        'm' | 'M' => (&value[..value.len() - 1], 1e6),
+        'g' | 'G' => (&value[..value.len() - 1], 1e9),
+        _ => (value, 1.0),
+    };
+
+    number.parse::<f64>().ok().map(|n| n * factor)
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some(ch) if ch == c => break,
+                        Some(ch) => value.push(ch),
+                        None => return Err("Unterminated string in filter expression".to_string()),
+                    }
+                }
+                tokens.push(Token::Word(value));
+            }
+            '&' | '|' | '=' | '!' | '<' | '>' => {
+                chars.next();
+                let next = chars.peek().copied();
+                let op = match (c, next) {
+                    ('&', Some('&')) => "&&",
+                    ('|', Some('|')) => "||",
+                    ('=', Some('=')) => "==",
+                    ('!', Some('=')) => "!=",
+                    ('<', Some('=')) => "<=",
+                    ('>', Some('=')) => ">=",
+                    ('=', _) => "=",
+                    ('!', _) => "!",
+                    ('<', _) => "<",
+                    ('>', _) => ">",
+                    _ => return Err(format!("Unexpected '{c}' in filter expression")),
+                };
+                if op.len() == 2 {
+                    chars.next();
+                }
+                tokens.push(Token::Op(op));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&ch) = chars.peek() {
+                    if ch.is_whitespace() || "()&|=!<>\"'".contains(ch) {
+                        break;
+                    }
+                    word.push(ch);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::{FormatInfo, StreamInfo};
+
+    fn info() -> MediaInfo {
+        MediaInfo {
+            streams: vec![
+                StreamInfo {
+                    index: 0,
+                    codec_type: Some("video".to_string()),
+                    codec_name: Some("hevc".to_string()),
+                    width: Some(1920),
+                    height: Some(1080),
+                    ..Default::default()
+                },
+                StreamInfo {
+                    index: 1,
+                    codec_type: Some("audio".to_string()),
+                    codec_name: Some("aac".to_string()),
+                    ..Default::default()
+                },
+            ],
+            format: FormatInfo {
+                format_name: Some("matroska,webm".to_string()),
+                duration: Some("120.5".to_string()),
+                bit_rate: Some("2500000".to_string()),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn parsed(source: &str) -> String {
+        Filter::parse(source).unwrap().expr.to_string()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            parsed("has_video || has_audio && width > 100"),
+            "(has_video || (has_audio && width > 100))"
+        );
+        assert_eq!(
+            parsed("(has_video || has_audio) && width > 100"),
+            "((has_video || has_audio) && width > 100)"
+        );
+        assert_eq!(
+            parsed("!has_subtitle && vcodec = hevc"),
+            "(!has_subtitle && vcodec == hevc)"
+        );
+    }
+
+    #[test]
+    fn evaluates_against_media_info() {
+        let info = info();
+        let matches = |source: &str| Filter::parse(source).unwrap().evaluate(&info).is_ok();
+
+        assert!(matches("has_video && vcodec == HEVC && duration > 60"));
+        assert!(matches("has_subtitle || acodec == aac"));
+        assert!(matches("bitrate >= 2.5M && width <= 1920"));
+        assert!(!matches("!has_audio || height < 720"));
+        assert_eq!(
+            Filter::parse("vcodec != hevc").unwrap().evaluate(&info),
+            Err("vcodec is hevc".to_string())
+        );
+    }
+
+    #[test]
+    fn quoted_values_keep_operators_and_spaces() {
+        assert_eq!(parsed("format == 'a && b'"), "format == a && b");
+        assert_eq!(parsed("vcodec != \"h 264\""), "vcodec != h 264");
+    }
+
+    #[test]
+    fn conditions_are_joined_with_and() {
+        let filter = Filter::parse_conditions("vcodec=hevc,acodec=aac").unwrap();
+        assert_eq!(filter.expr.to_string(), "(vcodec == hevc && acodec == aac)");
+
+        let filter = Filter::parse_conditions("format='mov,mp4',acodec=aac").unwrap();
+        assert_eq!(
+            filter.expr.to_string(),
+            "(format == mov,mp4 && acodec == aac)"
+        );
+    }
+
+    #[test]
+    fn missing_fields_are_not_equal_to_anything() {
+        let mut info = info();
+        info.streams.remove(0);
+        let matches = |source: &str| Filter::parse(source).unwrap().evaluate(&info).is_ok();
+
+        assert!(matches("vcodec != hevc"));
+        assert!(!matches("vcodec == hevc"));
+        assert!(!matches("width < 1280"));
+        assert!(matches("!(width >= 1280)"));
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        assert!(Filter::parse("fps > 30")
+            .unwrap_err()
+            .starts_with("Unknown filter field 'fps'"));
+        assert_eq!(
+            Filter::parse("(has_video").unwrap_err(),
+            "Missing ')' in filter expression"
+        );
+        assert_eq!(
+            Filter::parse("vcodec > hevc").unwrap_err(),
+            "'vcodec' can only be compared with == or !="
+        );
+        assert_eq!(
+            Filter::parse("width > wide").unwrap_err(),
+            "'wide' is not a valid number for 'width'"
+        );
+        assert_eq!(
+            Filter::parse("format == 'mkv").unwrap_err(),
+            "Unterminated string in filter expression"
+        );
+        assert_eq!(
+            Filter::parse("has_video has_audio").unwrap_err(),
+            "Unexpected 'has_audio' in filter expression"
+        );
+    }
+
+    #[test]
+    fn numbers_take_suffixes() {
+        assert_eq!(parse_number("2.5M"), Some(2.5e6));
+        assert_eq!(parse_number("800k"), Some(800e3));
+        assert_eq!(parse_number("1G"), Some(1e9));
+        assert_eq!(parse_number("60"), Some(60.0));
+        assert_eq!(parse_number("M"), None);
+    }
+}
@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Remembers which input and settings an output was created from, so `--skip-unchanged` can
+/// tell whether an existing output is still up to date.
+///
+/// Every fingerprint is appended to the store as one line, later lines replacing earlier ones
+/// for the same output, so recording one doesn't rewrite all of them.
+pub struct Fingerprints {
+    path: PathBuf,
+    entries: Mutex<HashMap<String, String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    output: String,
+    fingerprint: String,
+}
+
+impl Fingerprints {
+    pub fn load() -> Self {
+        let path = dirs::data_local_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("ffzap")
+            .join("fingerprints.jsonl");
+
+        let contents = fs::read_to_string(&path).unwrap_or_default();
+        let mut lines = 0;
+        let mut entries = HashMap::new();
+        for line in contents.lines() {
+            lines += 1;
+            if let Ok(entry) = serde_json::from_str::<Entry>(line) {
+                entries.insert(entry.output, entry.fingerprint);
+            }
+        }
+
+        let fingerprints = Fingerprints {
+            path,
+            entries: Mutex::new(entries),
+        };
+        // drop replaced and unreadable lines once per run, so the store doesn't keep growing
+        if lines > fingerprints.entries.lock().unwrap().len() {
+            let _ = fingerprints.compact();
+        }
+
+        fingerprints
+    }
+
+    pub fn matches(&self, output: &Path, fingerprint: &str) -> bool {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(&Self::key(output))
+            .is_some_and(|recorded| recorded == fingerprint)
+    }
+
+    /// Whether `output` was created by ffzap with `--skip-unchanged`
+    pub fn contains(&self, output: &Path) -> bool {
+        self.entries
+            .lock()
+            .unwrap()
+            .contains_key(&Self::key(output))
+    }
+
+    pub fn record(&self, output: &Path, fingerprint: String) -> io::Result<()> {
+        let output = Self::key(output);
+        let mut entries = self.entries.lock().unwrap();
+        if entries.get(&output) == Some(&fingerprint) {
+            return Ok(());
+        }
+
+        let mut line = serde_json::to_vec(&Entry {
+            output: output.clone(),
+            fingerprint: fingerprint.clone(),
+        })?;
+        line.push(b'\n');
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(&line)?;
+
+        entries.insert(output, fingerprint);
+        Ok(())
+    }
+
+    /// Hashes the contents of `input` together with the settings that were used to process it
+    pub fn compute(input: &Path, settings: &str) -> io::Result<String> {
+        // FNV-1a, so fingerprints stay stable across Rust versions
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut update = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+
+        let mut file = File::open(input)?;
+        let mut buffer = vec![0; 1024 * 1024];
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            update(&buffer[..read]);
+        }
+
+        update(&[0]);
+        update(settings.as_bytes());
+
+        Ok(format!("{hash:016x}"))
+    }
+
+    /// Outputs are stored with their directory resolved, so `out/a.mkv` and `./out/a.mkv`
+    /// share a fingerprint
+    fn key(output: &Path) -> String {
+        crate::resolve_output(output).to_string_lossy().to_string()
+    }
+
+    fn compact(&self) -> io::Result<()> {
+        let entries = self.entries.lock().unwrap();
+        let mut contents = vec![];
+        for (output, fingerprint) in entries.iter() {
+            serde_json::to_writer(
+                &mut contents,
+                &Entry {
+                    output: output.clone(),
+                    fingerprint: fingerprint.clone(),
+                },
+            )?;
+            contents.push(b'\n');
+        }
+
+        let tmp_path = self.path.with_extension("jsonl.tmp");
+        fs::write(&tmp_path, contents)?;
+        fs::rename(tmp_path, &self.path)
+    }
+}
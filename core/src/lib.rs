@@ -1,31 +1,121 @@
 pub mod args;
+pub mod concat;
+pub mod config;
+pub mod extract;
+pub mod filter;
+pub mod fingerprints;
+pub mod load;
 pub mod logger;
+pub mod loudnorm;
+pub mod manifest;
+pub mod preserve;
+pub mod probe;
 pub mod processor;
 pub mod progress;
+pub mod quality;
+pub mod queue;
+pub mod report;
+pub mod sanitize;
+pub mod segment;
+pub mod target_size;
+pub mod template;
+pub mod thumbs;
+pub mod watcher;
 
 pub use args::CmdArgs;
+pub use config::{Config, Preset};
+pub use filter::Filter;
+pub use load::ThreadCount;
 pub use logger::Logger;
+pub use probe::MediaInfo;
 pub use processor::Processor;
 pub use progress::Progress;
-use std::path::Path;
+pub use queue::Queue;
+pub use report::{Outcome, Report};
+pub use sanitize::Sanitize;
+use std::fs;
+use std::io::{self, ErrorKind, Read};
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use std::{fs, io::ErrorKind};
+use std::thread;
 #[cfg(feature = "ui")]
 use tauri::{AppHandle, Emitter};
+pub use template::OutputPattern;
 use walkdir::WalkDir;
 
+/// A file to process together with the directory it was found in
+#[derive(Debug, Clone)]
+pub struct InputFile {
+    pub path: PathBuf,
+    /// The directory that was passed to ffzap and contains `path`, used for `{{reldir}}`
+    pub base_dir: Option<PathBuf>,
+    /// 1-based position in the job, assigned when the file is queued
+    pub index: usize,
+    /// Replaces `--ffmpeg-options` for this file, set by a manifest
+    pub ffmpeg_options: Option<String>,
+    /// Replaces `--output` for this file, set by a manifest
+    pub output: Option<String>,
+}
+
+impl InputFile {
+    pub fn new(path: PathBuf, base_dir: Option<PathBuf>) -> Self {
+        InputFile {
+            path,
+            base_dir,
+            index: 0,
+            ffmpeg_options: None,
+            output: None,
+        }
+    }
+}
+
+/// Loads all paths to process. Returns the paths that should be processed and the paths
+/// that were skipped by `--filter`, together with the reason why.
 pub fn load_paths(
     cmd_args: &CmdArgs,
     #[cfg(feature = "ui")] app_handle: &AppHandle,
-) -> Vec<String> {
-    if let Some(input_file_path) = &cmd_args.file_list {
-        match fs::read_to_string(input_file_path) {
-            Ok(contents) => contents
-                .trim()
-                .split('\n')
-                .map(|s| s.trim().to_string())
+) -> (Vec<InputFile>, Vec<String>) {
+    let files = if let Some(manifest_path) = &cmd_args.manifest {
+        let manifest = read_file_list(manifest_path)
+            .map_err(|err| format!("Could not read {}: {err}", manifest_path.display()))
+            .and_then(|contents| {
+                String::from_utf8(contents).map_err(|_| {
+                    format!(
+                        "The contents of {} contain invalid data. Please make sure it is encoded as UTF-8.",
+                        manifest_path.display()
+                    )
+                })
+            })
+            .and_then(|contents| {
+                manifest::parse(&contents, cmd_args.base_dir.clone()).map_err(|err| {
+                    format!("Invalid manifest {}: {err}", manifest_path.display())
+                })
+            });
+
+        match manifest {
+            Ok(files) => files,
+            Err(error) => {
+                eprintln!("{}", error);
+                #[cfg(not(feature = "ui"))]
+                exit(1);
+
+                #[cfg(feature = "ui")]
+                {
+                    let _ = app_handle.emit("file-list-error", error);
+                    vec![]
+                }
+            }
+        }
+    } else if let Some(input_file_path) = &cmd_args.file_list {
+        match read_file_list(input_file_path)
+            .and_then(|contents| parse_file_list(&contents, cmd_args.null))
+        {
+            Ok(paths) => paths
+                .into_iter()
+                .map(|path| InputFile::new(path, cmd_args.base_dir.clone()))
                 .collect(),
             Err(err) => {
+                let input_file_path = input_file_path.display();
                 // blocks with without tauri code can't be reached from ui code because the file explorer
                 // prevents these
                 match err.kind() {
@@ -64,7 +154,7 @@ pub fn load_paths(
                     }
                     _ => {
                         let error = format!("An error has occurred reading the file at path {input_file_path}: {:?}.", err);
-                        eprintln!("{}", err);
+                        eprintln!("{}", error);
                         #[cfg(not(feature = "ui"))]
                         exit(1);
 
@@ -79,14 +169,17 @@ pub fn load_paths(
         }
     } else {
         let paths = cmd_args.input.clone().unwrap();
-        let mut files: Vec<String> = vec![];
+        let mut files: Vec<InputFile> = vec![];
 
-        for p in paths {
-            let path = Path::new(&p);
+        for path in paths {
             if path.is_file() {
-                files.push(p);
+                let base_dir = cmd_args
+                    .base_dir
+                    .clone()
+                    .or_else(|| path.parent().map(Path::to_path_buf));
+                files.push(InputFile::new(path, base_dir));
             } else if path.is_dir() {
-                for entry in WalkDir::new(path)
+                for entry in WalkDir::new(&path)
                     .follow_links(false)
                     .into_iter()
                     .filter_entry(|e| !e.path_is_symlink())
@@ -94,13 +187,17 @@ pub fn load_paths(
                     match entry {
                         Ok(entry) => {
                             if entry.file_type().is_file() {
-                                files.push(entry.path().to_str().unwrap().to_string());
+                                let base_dir = cmd_args.base_dir.as_ref().unwrap_or(&path);
+                                files.push(InputFile::new(
+                                    entry.into_path(),
+                                    Some(base_dir.clone()),
+                                ));
                             }
                         }
                         Err(err) => {
                             let error = format!(
                                 "Failed to read directory {}: {}",
-                                &err.path().unwrap().display(),
+                                err.path().unwrap_or(&path).display(),
                                 err
                             );
 
@@ -116,5 +213,159 @@ pub fn load_paths(
         }
 
         files
+    };
+
+    match &cmd_args.filter {
+        Some(filter) => match Filter::parse(filter) {
+            Ok(filter) => apply_filter(
+                files,
+                &filter,
+                cmd_args.thread_count.max(cmd_args.max_threads),
+            ),
+            Err(err) => {
+                let error = format!("Invalid filter expression: {err}");
+                eprintln!("{}", error);
+                #[cfg(not(feature = "ui"))]
+                exit(1);
+
+                #[cfg(feature = "ui")]
+                {
+                    let _ = app_handle.emit("file-list-error", error);
+                    (vec![], vec![])
+                }
+            }
+        },
+        None => (files, vec![]),
+    }
+}
+
+/// Reads the file list at `path`, or stdin if `path` is `-`
+fn read_file_list(path: &Path) -> io::Result<Vec<u8>> {
+    if path == Path::new("-") {
+        let mut bytes = vec![];
+        io::stdin().read_to_end(&mut bytes)?;
+        Ok(bytes)
+    } else {
+        fs::read(path)
+    }
+}
+
+/// Splits a file list into paths. Lists are either NUL-separated (e.g. from `find -print0`) or
+/// have one path per line, where blank lines and lines starting with `#` are ignored.
+fn parse_file_list(contents: &[u8], null_separated: bool) -> io::Result<Vec<PathBuf>> {
+    let entries: Vec<&[u8]> = if null_separated {
+        contents
+            .split(|byte| *byte == b'\0')
+            .filter(|path| !path.is_empty())
+            .collect()
+    } else {
+        contents
+            .split(|byte| *byte == b'\n')
+            .map(|line| line.trim_ascii())
+            .filter(|line| !line.is_empty() && !line.starts_with(b"#"))
+            .collect()
+    };
+
+    entries.into_iter().map(bytes_to_path).collect()
+}
+
+#[cfg(unix)]
+fn bytes_to_path(bytes: &[u8]) -> io::Result<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+
+    Ok(PathBuf::from(std::ffi::OsStr::from_bytes(bytes)))
+}
+
+// paths on other platforms aren't arbitrary bytes, so file lists have to be UTF-8 there
+#[cfg(not(unix))]
+fn bytes_to_path(bytes: &[u8]) -> io::Result<PathBuf> {
+    std::str::from_utf8(bytes)
+        .map(PathBuf::from)
+        .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+}
+
+/// Probes all files in parallel and splits them into matching and skipped files
+fn apply_filter(
+    files: Vec<InputFile>,
+    filter: &Filter,
+    thread_count: u16,
+) -> (Vec<InputFile>, Vec<String>) {
+    let chunk_size = files.len().div_ceil(thread_count as usize).max(1);
+
+    let results: Vec<(InputFile, Result<(), String>)> = thread::scope(|scope| {
+        let handles: Vec<_> = files
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|file| {
+                            let result = MediaInfo::probe(&file.path)
+                                .and_then(|info| filter.evaluate(&info));
+                            (file.clone(), result)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    let mut matching = vec![];
+    let mut skipped = vec![];
+
+    for (file, result) in results {
+        match result {
+            Ok(_) => matching.push(file),
+            Err(reason) => skipped.push(format!("{} ({reason})", file.path.display())),
+        }
+    }
+
+    (matching, skipped)
+}
+
+/// `output` with its directory resolved like the paths of watcher events, so the same file
+/// is recognized however it was named
+pub(crate) fn resolve_output(output: &Path) -> PathBuf {
+    let output = std::path::absolute(output).unwrap_or_else(|_| output.to_path_buf());
+    match (output.parent().map(fs::canonicalize), output.file_name()) {
+        (Some(Ok(parent)), Some(name)) => parent.join(name),
+        _ => output,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_list_skips_comments_and_blank_lines() {
+        let contents =
+            b"# videos to encode\r\na.mkv\r\n\r\n  Sub Dir/b c.mp4  \r\n#c.mkv\r\nd#1.mkv";
+        let paths = parse_file_list(contents, false).unwrap();
+
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from("a.mkv"),
+                PathBuf::from("Sub Dir/b c.mp4"),
+                PathBuf::from("d#1.mkv")
+            ]
+        );
+    }
+
+    #[test]
+    fn null_separated_file_list_keeps_newlines_and_comments() {
+        let contents = b"./a\nb.mkv\0./# c.mkv\0\0";
+        let paths = parse_file_list(contents, true).unwrap();
+
+        assert_eq!(
+            paths,
+            [PathBuf::from("./a\nb.mkv"), PathBuf::from("./# c.mkv")]
+        );
     }
 }
@@ -0,0 +1,267 @@
+use crate::Logger;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use sysinfo::System;
+
+/// How often the load is sampled with `--thread-count auto`
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Above this CPU usage in percent, fewer files are processed at once
+const BUSY_CPU_USAGE: f32 = 90.0;
+
+/// Below this CPU usage in percent, more files are processed at once
+const IDLE_CPU_USAGE: f32 = 60.0;
+
+/// Below this share of available memory, fewer files are processed at once
+const LOW_MEMORY: f64 = 0.1;
+
+/// Above this share of available memory, more files may be processed at once
+const ENOUGH_MEMORY: f64 = 0.2;
+
+/// `--thread-count`: a fixed number of files processed at once, or `auto` to adjust it to the
+/// load of the system
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadCount {
+    Fixed(u16),
+    Auto,
+}
+
+impl ThreadCount {
+    /// Parses a number of at least 1 or `auto`, for use as a clap value parser
+    pub fn parse(value: &str) -> Result<ThreadCount, String> {
+        match value.trim() {
+            "auto" => Ok(ThreadCount::Auto),
+            value => match value.parse::<u16>() {
+                Ok(count) if count >= 1 => Ok(ThreadCount::Fixed(count)),
+                _ => Err(format!(
+                    "Invalid thread count '{value}', expected a number of at least 1 or auto"
+                )),
+            },
+        }
+    }
+
+    /// The most files processed at once: the fixed count, or `max_threads` for `auto`, which
+    /// defaults to the number of CPU cores
+    pub fn max(self, max_threads: Option<u16>) -> u16 {
+        match self {
+            ThreadCount::Fixed(count) => count,
+            ThreadCount::Auto => max_threads.unwrap_or_else(|| {
+                thread::available_parallelism().map_or(1, |cores| cores.get() as u16)
+            }),
+        }
+    }
+}
+
+impl fmt::Display for ThreadCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThreadCount::Fixed(count) => write!(f, "{count}"),
+            ThreadCount::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+/// A number, or "auto" as a string
+impl Serialize for ThreadCount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ThreadCount::Fixed(count) => serializer.serialize_u16(*count),
+            ThreadCount::Auto => serializer.serialize_str("auto"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ThreadCount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u16),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(count) => ThreadCount::parse(&count.to_string()),
+            Raw::Text(text) => ThreadCount::parse(&text),
+        }
+        .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Limits how many workers process a file at the same time. The limit can change while
+/// workers are waiting.
+pub struct Limiter {
+    /// Active workers and the limit
+    state: Mutex<(u16, u16)>,
+    changed: Condvar,
+}
+
+/// Keeps a worker counted as active until it's dropped
+pub struct Slot<'a> {
+    limiter: &'a Limiter,
+}
+
+impl Limiter {
+    pub fn new(limit: u16) -> Self {
+        Limiter {
+            state: Mutex::new((0, limit)),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Waits until fewer workers than the limit are active
+    pub fn acquire(&self) -> Slot<'_> {
+        let mut state = self.state.lock().unwrap();
+        while state.0 >= state.1 {
+            state = self.changed.wait(state).unwrap();
+        }
+        state.0 += 1;
+
+        Slot { limiter: self }
+    }
+
+    pub fn limit(&self) -> u16 {
+        self.state.lock().unwrap().1
+    }
+
+    pub fn set_limit(&self, limit: u16) {
+        self.state.lock().unwrap().1 = limit;
+        self.changed.notify_all();
+    }
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.limiter.state.lock().unwrap().0 -= 1;
+        self.limiter.changed.notify_all();
+    }
+}
+
+/// The workers of a job and the limit of how many of them are active at once
+pub struct Concurrency {
+    pub workers: u16,
+    pub limiter: Arc<Limiter>,
+    stop: Arc<AtomicBool>,
+    monitor: Option<JoinHandle<()>>,
+}
+
+impl Concurrency {
+    /// Starts monitoring the load for `auto`, beginning with `min_threads` active workers
+    pub fn start(
+        thread_count: ThreadCount,
+        min_threads: Option<u16>,
+        max_threads: Option<u16>,
+        logger: Arc<Logger>,
+        verbose: bool,
+    ) -> Self {
+        let max = thread_count.max(max_threads);
+        if thread_count != ThreadCount::Auto {
+            return Concurrency::fixed(max);
+        }
+
+        let min = min_threads.unwrap_or(1).min(max);
+        logger.log_main_info(
+            format!("Processing {min} to {max} files at once, depending on the load"),
+            verbose,
+        );
+        let limiter = Arc::new(Limiter::new(min));
+        let stop = Arc::new(AtomicBool::new(false));
+        let monitor = monitor(
+            Arc::clone(&limiter),
+            min,
+            max,
+            Arc::clone(&stop),
+            logger,
+            verbose,
+        );
+
+        Concurrency {
+            workers: max,
+            limiter,
+            stop,
+            monitor: Some(monitor),
+        }
+    }
+
+    pub fn fixed(workers: u16) -> Self {
+        Concurrency {
+            workers,
+            limiter: Arc::new(Limiter::new(workers)),
+            stop: Arc::new(AtomicBool::new(false)),
+            monitor: None,
+        }
+    }
+
+    /// Stops monitoring the load
+    pub fn finish(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(monitor) = self.monitor {
+            let _ = monitor.join();
+        }
+    }
+}
+
+/// Samples CPU usage, load average and available memory until `stop` is set, and moves the
+/// limit of `limiter` one step up or down between `min` and `max` after every sample.
+fn monitor(
+    limiter: Arc<Limiter>,
+    min: u16,
+    max: u16,
+    stop: Arc<AtomicBool>,
+    logger: Arc<Logger>,
+    verbose: bool,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut system = System::new();
+        let cores = thread::available_parallelism().map_or(1, |cores| cores.get()) as f64;
+        // CPU usage is measured between two refreshes, so the first one only sets the baseline.
+        // SAMPLE_INTERVAL is far above sysinfo::MINIMUM_CPU_UPDATE_INTERVAL, so every following
+        // refresh has a valid reading.
+        system.refresh_cpu_usage();
+
+        while !stop.load(Ordering::Relaxed) {
+            // sleep in short steps, so the job doesn't wait for the monitor when it's done
+            for _ in 0..SAMPLE_INTERVAL.as_millis() / 100 {
+                if stop.load(Ordering::Relaxed) {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+
+            system.refresh_cpu_usage();
+            system.refresh_memory();
+            let cpu_usage = system.global_cpu_usage();
+            // always 0 on Windows, where CPU usage has to do
+            let load = System::load_average().one;
+            let memory = match system.total_memory() {
+                0 => 1.0,
+                total => system.available_memory() as f64 / total as f64,
+            };
+
+            let limit = limiter.limit();
+            let new_limit = if cpu_usage > BUSY_CPU_USAGE || load > cores || memory < LOW_MEMORY {
+                limit.saturating_sub(1)
+            } else if cpu_usage < IDLE_CPU_USAGE && load < cores * 0.75 && memory > ENOUGH_MEMORY {
+                limit + 1
+            } else {
+                limit
+            }
+            .clamp(min, max);
+
+            if new_limit != limit {
+                limiter.set_limit(new_limit);
+                logger.log_main_info(
+                    format!(
+                        "Processing up to {new_limit} files at once (CPU usage {cpu_usage:.0}%, load {load:.2}, {:.0}% memory available)",
+                        memory * 100.0
+                    ),
+                    verbose,
+                );
+            }
+        }
+    })
+}
@@ -58,6 +58,42 @@ impl Logger {
         }
     }
 
+    /// Logs a line that doesn't belong to any worker thread
+    pub fn log_main_info(&self, line: String, print: bool) {
+        let line = format!("[INFO in MAIN] -- {line}");
+        let cyan_line = line.cyan().to_string();
+
+        self.write_to_log(&line);
+
+        if print {
+            #[cfg(feature = "ui")]
+            {
+                use tauri::Emitter;
+
+                let _ = self.app_handle.emit("log-update-info", &line);
+            }
+
+            self.print(cyan_line);
+        }
+    }
+
+    pub fn log_warning(&self, line: String, thread: u16, print: bool) {
+        let line = format!("[WARNING in THREAD {thread}] -- {line}");
+        let yellow_line = line.bright_yellow().to_string();
+        self.write_to_log(&line);
+
+        if print {
+            #[cfg(feature = "ui")]
+            {
+                use tauri::Emitter;
+
+                let _ = self.app_handle.emit("log-update-warning", &line);
+            }
+
+            self.print(yellow_line);
+        }
+    }
+
     pub fn log_error(&self, line: String, thread: u16, print: bool) {
         let line = format!("[ERROR in THREAD {thread}] -- {line}");
         let red_line = line.bright_red().to_string();
@@ -75,8 +111,26 @@ impl Logger {
         }
     }
 
+    /// Logs an error that doesn't belong to any worker thread
+    pub fn log_main_error(&self, line: String, print: bool) {
+        let line = format!("[ERROR in MAIN] -- {line}");
+        let red_line = line.bright_red().to_string();
+        self.write_to_log(&line);
+
+        if print {
+            #[cfg(feature = "ui")]
+            {
+                use tauri::Emitter;
+
+                let _ = self.app_handle.emit("log-update-error", &line);
+            }
+
+            self.print(red_line);
+        }
+    }
+
     pub fn append_failed_paths_to_log(&self, paths: &MutexGuard<Vec<String>>) {
-        if paths.len() == 0 {
+        if paths.is_empty() {
             return;
         }
 
@@ -89,7 +143,22 @@ impl Logger {
         self.write_to_log(&to_write);
     }
 
-    pub fn get_log_path(&self) -> Display {
+    pub fn append_skipped_paths_to_log(&self, paths: &[String]) {
+        if paths.is_empty() {
+            return;
+        }
+
+        let static_line =
+            "\nThe following files were skipped because they didn't match the filter:";
+
+        let paths_lines = paths.join("\n");
+
+        let to_write = format!("{}\n{}", static_line, paths_lines);
+
+        self.write_to_log(&to_write);
+    }
+
+    pub fn get_log_path(&self) -> Display<'_> {
         self.log_path.display()
     }
 
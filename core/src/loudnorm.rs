@@ -0,0 +1,194 @@
+use serde::Deserialize;
+#[cfg(target_os = "windows")]
+use std::os::windows::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// EBU R128 targets for `--loudnorm`, e.g. `I=-16:TP=-1.5:LRA=11`
+#[derive(Debug, Clone, Copy)]
+pub struct Loudnorm {
+    integrated: f64,
+    true_peak: f64,
+    range: f64,
+}
+
+/// What the analysis pass of the loudnorm filter measured
+#[derive(Debug, Deserialize)]
+pub struct Measurement {
+    input_i: String,
+    input_tp: String,
+    input_lra: String,
+    input_thresh: String,
+    target_offset: String,
+}
+
+impl Loudnorm {
+    /// Parses `key=value` pairs separated by `:`. Missing values use the defaults of ffmpeg.
+    pub fn parse(value: &str) -> Result<Loudnorm, String> {
+        let mut loudnorm = Loudnorm {
+            integrated: -24.0,
+            true_peak: -2.0,
+            range: 7.0,
+        };
+
+        for pair in value.split(':').filter(|pair| !pair.is_empty()) {
+            let Some((key, number)) = pair.split_once('=') else {
+                return Err(format!(
+                    "Invalid value '{pair}', expected something like I=-16"
+                ));
+            };
+            let number = number
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| format!("Invalid number '{number}' for {key}"))?;
+
+            let (target, min, max) = match key.trim().to_uppercase().as_str() {
+                "I" => (&mut loudnorm.integrated, -70.0, -5.0),
+                "TP" => (&mut loudnorm.true_peak, -9.0, 0.0),
+                "LRA" => (&mut loudnorm.range, 1.0, 50.0),
+                _ => {
+                    return Err(format!(
+                        "Unknown key '{key}'. Available keys are: I, TP, LRA"
+                    ))
+                }
+            };
+
+            if !(min..=max).contains(&number) {
+                return Err(format!("{key} has to be between {min} and {max}"));
+            }
+            *target = number;
+        }
+
+        Ok(loudnorm)
+    }
+
+    /// Runs the analysis pass over the audio of `path`
+    pub fn measure(&self, path: &Path) -> Result<Measurement, String> {
+        let mut command = Command::new("ffmpeg");
+        command
+            .arg("-hide_banner")
+            .arg("-i")
+            .arg(path)
+            .arg("-af")
+            .arg(format!("{}:print_format=json", self.targets()))
+            .args(["-vn", "-sn", "-f", "null", "-"]);
+        command.stdout(Stdio::null());
+        command.stderr(Stdio::piped());
+        #[cfg(target_os = "windows")]
+        command.creation_flags(0x08000000); // don't show cmd windows on Windows
+
+        let output = command
+            .output()
+            .map_err(|err| format!("Could not run ffmpeg: {err}"))?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        if !output.status.success() {
+            return Err(format!("The analysis failed: {}", stderr.trim()));
+        }
+
+        // the measurement is the last JSON object ffmpeg prints
+        let json = match (stderr.rfind('{'), stderr.rfind('}')) {
+            (Some(start), Some(end)) if start < end => &stderr[start..=end],
+            _ => return Err("ffmpeg didn't print a measurement".to_string()),
+        };
+
+        serde_json::from_str(json).map_err(|err| format!("Could not parse the measurement: {err}"))
+    }
+
+    /// The filter for the encode pass, which corrects the measured loudness linearly
+    pub fn filter(&self, measurement: &Measurement) -> String {
+        format!(
+            "{}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true",
+            self.targets(),
+            measurement.input_i,
+            measurement.input_tp,
+            measurement.input_lra,
+            measurement.input_thresh,
+            measurement.target_offset
+        )
+    }
+
+    /// The options of the encode pass. The loudnorm filter outputs 192 kHz audio, so it's
+    /// resampled to the `sample_rate` of the input unless `ffmpeg_options` set one. Encoders
+    /// that don't support that rate still get the closest one they do.
+    pub fn encode_args(
+        &self,
+        measurement: &Measurement,
+        sample_rate: Option<&str>,
+        ffmpeg_options: &[&str],
+    ) -> Vec<String> {
+        let mut filter = self.filter(measurement);
+        let sets_sample_rate = ffmpeg_options
+            .iter()
+            .any(|option| *option == "-ar" || option.starts_with("-ar:"));
+        if let (Some(sample_rate), false) = (sample_rate, sets_sample_rate) {
+            filter.push_str(&format!(",aresample={sample_rate}"));
+        }
+
+        vec!["-af".to_string(), filter]
+    }
+
+    fn targets(&self) -> String {
+        format!(
+            "loudnorm=I={}:TP={}:LRA={}",
+            self.integrated, self.true_peak, self.range
+        )
+    }
+}
+
+impl Measurement {
+    /// Silence can't be normalized, ffmpeg reports its loudness as -inf
+    pub fn is_silent(&self) -> bool {
+        !self.input_i.parse::<f64>().is_ok_and(f64::is_finite)
+    }
+}
+
+/// Checks `--loudnorm` for use as a clap value parser.
+pub fn validate(value: &str) -> Result<String, String> {
+    Loudnorm::parse(value).map(|_| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measurement() -> Measurement {
+        Measurement {
+            input_i: "-27.61".to_string(),
+            input_tp: "-4.47".to_string(),
+            input_lra: "18.06".to_string(),
+            input_thresh: "-39.20".to_string(),
+            target_offset: "0.58".to_string(),
+        }
+    }
+
+    #[test]
+    fn keeps_the_sample_rate_of_the_input() {
+        let loudnorm = Loudnorm::parse("I=-16").unwrap();
+
+        assert_eq!(
+            loudnorm.encode_args(&measurement(), Some("44100"), &["-c:a", "aac"]),
+            [
+                "-af",
+                "loudnorm=I=-16:TP=-2:LRA=7:measured_I=-27.61:measured_TP=-4.47:measured_LRA=18.06:measured_thresh=-39.20:offset=0.58:linear=true,aresample=44100"
+            ]
+        );
+        assert!(
+            !loudnorm.encode_args(&measurement(), Some("44100"), &["-ar", "48000"])[1]
+                .contains("aresample")
+        );
+        assert!(!loudnorm.encode_args(&measurement(), None, &[])[1].contains("aresample"));
+    }
+
+    #[test]
+    fn rejects_invalid_targets() {
+        assert_eq!(
+            Loudnorm::parse("I=-80").unwrap_err(),
+            "I has to be between -70 and -5"
+        );
+        assert_eq!(
+            Loudnorm::parse("X=1").unwrap_err(),
+            "Unknown key 'X'. Available keys are: I, TP, LRA"
+        );
+    }
+}
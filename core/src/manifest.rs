@@ -0,0 +1,219 @@
+use crate::InputFile;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// One row of a manifest: the file to process and the settings that differ from the job
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Row {
+    input: PathBuf,
+    #[serde(default)]
+    ffmpeg_options: Option<String>,
+    #[serde(default)]
+    output: Option<String>,
+}
+
+/// Parses a manifest. Manifests are either JSON lines, one object per line:
+///
+/// ```text
+/// {"input": "a.mkv", "ffmpeg_options": "-c:v libx265 -crf 20"}
+/// {"input": "b.mkv", "output": "special/b.mp4"}
+/// ```
+///
+/// or CSV with a header naming the columns `input`, `ffmpeg_options` and `output`. Empty
+/// values use the options and output pattern of the job. Options are split at spaces like
+/// `--ffmpeg-options`, so quotes inside them don't keep a value with spaces together.
+pub fn parse(contents: &str, base_dir: Option<PathBuf>) -> Result<Vec<InputFile>, String> {
+    // spreadsheet programs like to start CSV exports with a byte order mark
+    let contents = contents.strip_prefix('\u{feff}').unwrap_or(contents);
+    let is_json = contents
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .is_some_and(|line| line.starts_with('{'));
+
+    let rows = match is_json {
+        true => parse_json_lines(contents)?,
+        false => parse_csv(contents)?,
+    };
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let mut file = InputFile::new(row.input, base_dir.clone());
+            file.ffmpeg_options = row.ffmpeg_options.filter(|options| !options.is_empty());
+            file.output = row.output.filter(|output| !output.is_empty());
+            file
+        })
+        .collect())
+}
+
+fn parse_json_lines(contents: &str) -> Result<Vec<Row>, String> {
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| serde_json::from_str(line).map_err(|err| format!("Line {}: {err}", i + 1)))
+        .collect()
+}
+
+fn parse_csv(contents: &str) -> Result<Vec<Row>, String> {
+    let mut records = parse_csv_records(contents)?.into_iter();
+    let header = records.next().ok_or("The manifest is empty")?;
+
+    let mut input_column = None;
+    let mut options_column = None;
+    let mut output_column = None;
+    for (i, name) in header.iter().enumerate() {
+        match name.trim() {
+            "input" => input_column = Some(i),
+            "ffmpeg_options" => options_column = Some(i),
+            "output" => output_column = Some(i),
+            other => {
+                return Err(format!(
+                    "Unknown column '{other}'. Available columns are: input, ffmpeg_options, output"
+                ))
+            }
+        }
+    }
+    let input_column = input_column.ok_or("The manifest has no 'input' column")?;
+
+    records
+        .enumerate()
+        .map(|(i, record)| {
+            let column = |column: Option<usize>| {
+                column
+                    .and_then(|column| record.get(column))
+                    .filter(|value| !value.is_empty())
+                    .cloned()
+            };
+
+            match column(Some(input_column)) {
+                Some(input) => Ok(Row {
+                    input: PathBuf::from(input),
+                    ffmpeg_options: column(options_column),
+                    output: column(output_column),
+                }),
+                None => Err(format!("Row {}: the input is missing", i + 2)),
+            }
+        })
+        .collect()
+}
+
+/// Splits CSV into records. Fields can be quoted to contain commas, line breaks or `""`.
+fn parse_csv_records(contents: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = vec![];
+    let mut record = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = contents.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => record.push(std::mem::take(&mut field)),
+            ('\r', false) => {}
+            ('\n', false) => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            (c, _) => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err("Unterminated quote at the end of the manifest".to_string());
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+
+    // blank lines don't count as rows
+    records.retain(|record| !(record.len() == 1 && record[0].trim().is_empty()));
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_fields_can_contain_commas_quotes_and_line_breaks() {
+        let contents = "input,ffmpeg_options,output\r\n\
+            \"a \"\"b\"\", c.mkv\",\"-vf scale=1280:-2,fps=30\",\r\n\
+            \r\n\
+            c.mkv,,\"line\nbreak.mp4\"\r\n";
+        let files = parse(contents, None).unwrap();
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, PathBuf::from("a \"b\", c.mkv"));
+        assert_eq!(
+            files[0].ffmpeg_options.as_deref(),
+            Some("-vf scale=1280:-2,fps=30")
+        );
+        assert_eq!(files[0].output, None);
+        assert_eq!(files[1].path, PathBuf::from("c.mkv"));
+        assert_eq!(files[1].ffmpeg_options, None);
+        assert_eq!(files[1].output.as_deref(), Some("line\nbreak.mp4"));
+    }
+
+    #[test]
+    fn skips_byte_order_mark() {
+        let files = parse("\u{feff}input,output\na.mkv,out.mp4", None).unwrap();
+        assert_eq!(files[0].path, PathBuf::from("a.mkv"));
+
+        let files = parse("\u{feff}{\"input\": \"b.mkv\"}", None).unwrap();
+        assert_eq!(files[0].path, PathBuf::from("b.mkv"));
+    }
+
+    #[test]
+    fn csv_columns_can_be_in_any_order() {
+        let files = parse("output,input\nout.mp4,a.mkv", None).unwrap();
+
+        assert_eq!(files[0].path, PathBuf::from("a.mkv"));
+        assert_eq!(files[0].output.as_deref(), Some("out.mp4"));
+    }
+
+    #[test]
+    fn rejects_invalid_csv() {
+        assert_eq!(
+            parse("input,crf\na.mkv,20", None).unwrap_err(),
+            "Unknown column 'crf'. Available columns are: input, ffmpeg_options, output"
+        );
+        assert_eq!(
+            parse("output\nout.mp4", None).unwrap_err(),
+            "The manifest has no 'input' column"
+        );
+        assert_eq!(
+            parse("input,output\n,out.mp4", None).unwrap_err(),
+            "Row 2: the input is missing"
+        );
+        assert_eq!(
+            parse("input\n\"a.mkv", None).unwrap_err(),
+            "Unterminated quote at the end of the manifest"
+        );
+    }
+
+    #[test]
+    fn parses_json_lines() {
+        let contents = "{\"input\": \"a.mkv\", \"ffmpeg_options\": \"-crf 20\"}\n\n{\"input\": \"b.mkv\", \"output\": \"\"}\n";
+        let files = parse(contents, Some(PathBuf::from("in"))).unwrap();
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].ffmpeg_options.as_deref(), Some("-crf 20"));
+        assert_eq!(files[0].base_dir, Some(PathBuf::from("in")));
+        assert_eq!(files[1].path, PathBuf::from("b.mkv"));
+        assert_eq!(files[1].output, None);
+
+        assert!(parse("{\"input\": \"a.mkv\"}\n{\"file\": \"b.mkv\"}", None)
+            .unwrap_err()
+            .starts_with("Line 2: unknown field `file`"));
+    }
+}
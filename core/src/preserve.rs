@@ -0,0 +1,54 @@
+use std::fs::{File, FileTimes};
+use std::io;
+use std::path::Path;
+
+/// Gives `output` the access and modification times of `input`
+pub fn copy_times(input: &Path, output: &Path) -> io::Result<()> {
+    let metadata = input.metadata()?;
+    let times = FileTimes::new()
+        .set_accessed(metadata.accessed()?)
+        .set_modified(metadata.modified()?);
+
+    File::options().write(true).open(output)?.set_times(times)
+}
+
+/// Copies the extended attributes of `input` to `output`. Attributes the file system of
+/// `output` doesn't support are skipped.
+#[cfg(unix)]
+pub fn copy_xattrs(input: &Path, output: &Path) -> io::Result<()> {
+    for name in xattr::list(input)? {
+        if let Some(value) = xattr::get(input, &name)? {
+            match xattr::set(output, &name, &value) {
+                Err(err) if err.kind() == io::ErrorKind::Unsupported => {}
+                result => result?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn copy_xattrs(_input: &Path, _output: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "extended attributes are only supported on Unix",
+    ))
+}
+
+/// Gives `output` the owner and group of `input`. Changing the owner usually requires root.
+#[cfg(unix)]
+pub fn copy_owner(input: &Path, output: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{chown, MetadataExt};
+
+    let metadata = input.metadata()?;
+    chown(output, Some(metadata.uid()), Some(metadata.gid()))
+}
+
+#[cfg(not(unix))]
+pub fn copy_owner(_input: &Path, _output: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "owners are only supported on Unix",
+    ))
+}
@@ -0,0 +1,100 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+#[cfg(target_os = "windows")]
+use std::os::windows::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// The parts of `ffprobe -show_format -show_streams` output ffzap cares about.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct MediaInfo {
+    #[serde(default)]
+    pub streams: Vec<StreamInfo>,
+    #[serde(default)]
+    pub format: FormatInfo,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct StreamInfo {
+    pub index: u32,
+    pub codec_type: Option<String>,
+    pub codec_name: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub pix_fmt: Option<String>,
+    /// e.g. 30000/1001
+    pub r_frame_rate: Option<String>,
+    /// e.g. 1/90000
+    pub time_base: Option<String>,
+    pub profile: Option<String>,
+    pub sample_rate: Option<String>,
+    pub channels: Option<u32>,
+    pub channel_layout: Option<String>,
+    pub bit_rate: Option<String>,
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct FormatInfo {
+    pub format_name: Option<String>,
+    pub duration: Option<String>,
+    pub bit_rate: Option<String>,
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+}
+
+impl MediaInfo {
+    pub fn probe(path: &Path) -> Result<MediaInfo, String> {
+        let mut command = Command::new("ffprobe");
+        command
+            .args([
+                "-v",
+                "error",
+                "-print_format",
+                "json",
+                "-show_format",
+                "-show_streams",
+            ])
+            .arg(path);
+        command.stdin(Stdio::null());
+        command.stdout(Stdio::piped());
+        command.stderr(Stdio::piped());
+        #[cfg(target_os = "windows")]
+        command.creation_flags(0x08000000); // don't show cmd windows on Windows
+
+        let output = command.output().map_err(|_| {
+            "There was an error running ffprobe. Please check if it's correctly installed and working as intended.".to_string()
+        })?;
+
+        if !output.status.success() {
+            return Err(format!(
+                "ffprobe could not read the file: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        serde_json::from_slice(&output.stdout)
+            .map_err(|err| format!("Could not parse ffprobe output: {err}"))
+    }
+
+    pub fn first_stream(&self, codec_type: &str) -> Option<&StreamInfo> {
+        self.streams
+            .iter()
+            .find(|s| s.codec_type.as_deref() == Some(codec_type))
+    }
+
+    pub fn has_stream(&self, codec_type: &str) -> bool {
+        self.first_stream(codec_type).is_some()
+    }
+
+    /// Duration of the container in seconds
+    pub fn duration(&self) -> Option<f64> {
+        self.format.duration.as_ref()?.parse().ok()
+    }
+
+    /// Overall bit rate of the container in bits per second
+    pub fn bit_rate(&self) -> Option<u64> {
+        self.format.bit_rate.as_ref()?.parse().ok()
+    }
+}
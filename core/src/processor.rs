@@ -1,20 +1,180 @@
-use crate::{Logger, Progress};
-use std::ffi::OsStr;
-use std::fs::{create_dir_all, remove_file};
-use std::io::ErrorKind;
+use crate::args::{self, ConcatArgs, ExtractArgs, ThumbsArgs};
+use crate::concat;
+use crate::extract;
+use crate::fingerprints::Fingerprints;
+use crate::load::Concurrency;
+use crate::loudnorm::Loudnorm;
+use crate::preserve;
+use crate::quality;
+use crate::queue::Queue;
+use crate::report::{Outcome, Report};
+use crate::segment;
+use crate::target_size;
+use crate::template::TemplateContext;
+use crate::thumbs;
+use crate::{
+    CmdArgs, Config, Filter, InputFile, Logger, MediaInfo, OutputPattern, Progress, Sanitize,
+};
+use chrono::Local;
+use std::collections::{HashMap, HashSet};
+use std::fs::{canonicalize, copy, create_dir_all, read_dir, remove_dir_all, remove_file, rename};
+use std::io::{self, ErrorKind};
 #[cfg(target_os = "windows")]
 use std::os::windows::process::CommandExt;
-use std::path::Path;
-use std::process::{Command, Stdio};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
 use std::sync::{Arc, Mutex};
 use std::thread;
 #[cfg(feature = "ui")]
 use tauri::AppHandle;
 
+/// One output of a job: the ffmpeg options and the pattern of the file they're written to
+#[derive(Clone)]
+struct Rendition {
+    ffmpeg_options: Option<String>,
+    pattern: OutputPattern,
+    /// Number of the image, for `ffzap thumbs`
+    frame: Option<usize>,
+    /// Index of the stream, for `ffzap extract`
+    stream: Option<u32>,
+}
+
+impl Rendition {
+    /// The main output from `--ffmpeg-options` and `--output`, followed by every `--extra-output`
+    fn from_args(cmd_args: &CmdArgs) -> Result<Vec<Rendition>, String> {
+        let extra_outputs = cmd_args.extra_output.chunks(2).map(|pair| {
+            (
+                Some(pair[0].clone()),
+                pair.get(1).cloned().unwrap_or_default(),
+            )
+        });
+
+        std::iter::once((cmd_args.ffmpeg_options.clone(), cmd_args.output.clone()))
+            .chain(extra_outputs)
+            .map(|(ffmpeg_options, output)| {
+                Ok(Rendition {
+                    ffmpeg_options: ffmpeg_options.filter(|options| !options.is_empty()),
+                    pattern: Self::parse_pattern(&output, cmd_args.ext_map.as_deref())?,
+                    frame: None,
+                    stream: None,
+                })
+            })
+            .collect()
+    }
+
+    /// The renditions of `file`, with the overrides from a manifest applied to the main output
+    fn for_file(
+        renditions: &[Rendition],
+        file: &InputFile,
+        ext_map: Option<&str>,
+    ) -> Result<Vec<Rendition>, String> {
+        let mut renditions = renditions.to_vec();
+
+        if let Some(ffmpeg_options) = &file.ffmpeg_options {
+            renditions[0].ffmpeg_options = Some(ffmpeg_options.clone());
+        }
+        if let Some(output) = &file.output {
+            renditions[0].pattern = Self::parse_pattern(output, ext_map)?;
+        }
+
+        Ok(renditions)
+    }
+
+    /// One image per entry of `image_options`, written to the pattern of `base`. The ffmpeg
+    /// options of `base` are added to the options of every image.
+    fn thumbnails(base: &Rendition, image_options: Vec<String>) -> Vec<Rendition> {
+        image_options
+            .into_iter()
+            .enumerate()
+            .map(|(i, options)| Rendition {
+                ffmpeg_options: Some(match &base.ffmpeg_options {
+                    Some(base_options) => format!("{options} {base_options}"),
+                    None => options,
+                }),
+                pattern: base.pattern.clone(),
+                frame: Some(i + 1),
+                stream: None,
+            })
+            .collect()
+    }
+
+    /// One output per entry of `stream_options`, written to the pattern of `base`. The ffmpeg
+    /// options of `base` are added to the options of every stream.
+    fn streams(base: &Rendition, stream_options: Vec<(u32, String)>) -> Vec<Rendition> {
+        stream_options
+            .into_iter()
+            .map(|(index, options)| Rendition {
+                ffmpeg_options: Some(match &base.ffmpeg_options {
+                    Some(base_options) => format!("{options} {base_options}"),
+                    None => options,
+                }),
+                pattern: base.pattern.clone(),
+                frame: None,
+                stream: Some(index),
+            })
+            .collect()
+    }
+
+    fn parse_pattern(output: &str, ext_map: Option<&str>) -> Result<OutputPattern, String> {
+        let mut pattern = OutputPattern::parse(output)?;
+        if let Some(ext_map) = ext_map {
+            pattern.map_extensions(ext_map)?;
+        }
+
+        Ok(pattern)
+    }
+
+    fn split_options(&self) -> Vec<&str> {
+        match &self.ffmpeg_options {
+            Some(options) => options.split(' ').collect::<Vec<&str>>(),
+            None => vec![],
+        }
+    }
+}
+
+/// Which metadata of a source is copied to its outputs
+#[derive(Debug, Clone, Copy)]
+struct Preserve {
+    times: bool,
+    xattrs: bool,
+    owner: bool,
+}
+
+impl Preserve {
+    fn from_args(cmd_args: &CmdArgs) -> Self {
+        Preserve {
+            times: cmd_args.preserve_times,
+            xattrs: cmd_args.preserve_xattrs,
+            owner: cmd_args.preserve_owner,
+        }
+    }
+}
+
+/// Replaces the ffmpeg options of files that match `--remux-if`
+const REMUX_OPTIONS: &str = "-map 0 -c copy";
+
+/// How often a file is encoded until it fits into `--target-size`
+const MAX_TARGET_SIZE_ATTEMPTS: u32 = 3;
+
+/// Parses the margin of `--keep-smaller` in percent for use as a clap value parser, e.g. `10`
+/// or `10%`.
+pub fn parse_margin(value: &str) -> Result<f64, String> {
+    match value.trim().trim_end_matches('%').parse::<f64>() {
+        Ok(margin) if (0.0..100.0).contains(&margin) => Ok(margin),
+        _ => Err(format!(
+            "Invalid margin '{value}', expected a percentage from 0 to 99"
+        )),
+    }
+}
+
 pub struct Processor {
     logger: Arc<Logger>,
     progress: Arc<Progress>,
     failed_paths: Arc<Mutex<Vec<String>>>,
+    up_to_date_paths: Arc<Mutex<Vec<String>>>,
+    report: Arc<Report>,
+    written_outputs: Arc<Mutex<HashSet<PathBuf>>>,
+    sanitized_outputs: Arc<Mutex<HashMap<PathBuf, PathBuf>>>,
 }
 
 impl Processor {
@@ -23,44 +183,430 @@ impl Processor {
             logger,
             progress,
             failed_paths: Arc::new(Mutex::new(vec![])),
+            up_to_date_paths: Arc::new(Mutex::new(vec![])),
+            report: Arc::new(Report::new()),
+            written_outputs: Arc::new(Mutex::new(HashSet::new())),
+            sanitized_outputs: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
     pub fn process_files(
         &self,
-        paths: Vec<String>,
-        thread_count: u16,
-        ffmpeg_options: Option<String>,
-        output_pattern: String,
-        overwrite: bool,
-        verbose: bool,
-        delete: bool,
+        paths: Vec<InputFile>,
+        cmd_args: &CmdArgs,
         #[cfg(feature = "ui")] app_handle: AppHandle,
     ) {
-        let paths = Arc::new(Mutex::new(paths));
+        self.process_queue(
+            Arc::new(Queue::from_paths(paths)),
+            cmd_args,
+            #[cfg(feature = "ui")]
+            app_handle,
+        );
+    }
+
+    /// Joins the files of every group into one output, for `ffzap concat`. Groups are
+    /// processed in parallel and each one counts as a single file in the progress.
+    pub fn concat_groups(
+        &self,
+        groups: Vec<Vec<InputFile>>,
+        cmd_args: &CmdArgs,
+        concat_args: &ConcatArgs,
+        #[cfg(feature = "ui")] app_handle: AppHandle,
+    ) {
+        // these work on single files, a group is only joined
+        let unsupported = [
+            (cmd_args.skip_unchanged, "--skip-unchanged"),
+            (cmd_args.two_pass, "--two-pass"),
+            (cmd_args.target_size.is_some(), "--target-size"),
+            (cmd_args.loudnorm.is_some(), "--loudnorm"),
+            (
+                cmd_args.quality_check
+                    || cmd_args.min_ssim.is_some()
+                    || cmd_args.min_psnr.is_some(),
+                "--quality-check",
+            ),
+            (cmd_args.keep_smaller.is_some(), "--keep-smaller"),
+            (cmd_args.segment_length.is_some(), "--segment-length"),
+            (!cmd_args.extra_output.is_empty(), "--extra-output"),
+            (cmd_args.skip_if.is_some(), "--skip-if"),
+            (cmd_args.remux_if.is_some(), "--remux-if"),
+            (
+                groups
+                    .iter()
+                    .flatten()
+                    .any(|file| file.ffmpeg_options.is_some() || file.output.is_some()),
+                "Settings per file in --manifest",
+            ),
+        ];
+        if let Some((_, name)) = unsupported.iter().find(|(used, _)| *used) {
+            self.logger
+                .log_main_error(format!("{name} can't be used with concat"), true);
+            return;
+        }
+
+        let rendition = match Rendition::from_args(cmd_args) {
+            Ok(renditions) => renditions[0].clone(),
+            Err(err) => {
+                self.logger
+                    .log_main_error(format!("Invalid output pattern: {err}"), true);
+                return;
+            }
+        };
+        let preserve = Preserve::from_args(cmd_args);
+        let total = groups.len();
+        let groups = Mutex::new(groups.into_iter());
+        let started = Local::now();
+        let verbose = cmd_args.verbose;
+
+        let concurrency = Concurrency::start(
+            cmd_args.thread_count,
+            cmd_args.min_threads,
+            cmd_args.max_threads,
+            Arc::clone(&self.logger),
+            verbose,
+        );
+
+        self.progress.start_stick(1000);
+
+        thread::scope(|scope| {
+            for thread in 0..concurrency.workers {
+                let groups = &groups;
+                let rendition = &rendition;
+                let limiter = &concurrency.limiter;
+                #[cfg(feature = "ui")]
+                let app_handle = app_handle.clone();
+
+                scope.spawn(move || loop {
+                    let _slot = limiter.acquire();
+                    let Some(files) = groups.lock().unwrap().next() else {
+                        break;
+                    };
+                    let paths = files.iter().map(|file| file.path.clone()).collect::<Vec<_>>();
+                    let first = paths[0].as_path();
+                    let fail = |reason: String| {
+                        self.logger.log_error(
+                            format!("Could not join {}: {reason}. Continuing with next task if there's more to do...", first.display()),
+                            thread,
+                            verbose,
+                        );
+                        self.report
+                            .record(first, &[] as &[PathBuf], Outcome::Failed, Some(reason));
+                        let mut failed_paths = self.failed_paths.lock().unwrap();
+                        failed_paths.extend(paths.iter().map(|path| path.display().to_string()));
+                    };
+
+                    self.logger.log_info(
+                        format!("Joining {} files starting with {}", paths.len(), first.display()),
+                        thread,
+                        verbose,
+                    );
+
+                    let infos = match paths
+                        .iter()
+                        .map(|path| {
+                            MediaInfo::probe(path).map_err(|err| {
+                                format!("Could not read metadata of {}: {err}", path.display())
+                            })
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                    {
+                        Ok(infos) => infos,
+                        Err(err) => {
+                            fail(err);
+                            continue;
+                        }
+                    };
+
+                    let context = TemplateContext {
+                        path: first,
+                        base_dir: files[0].base_dir.as_deref(),
+                        media_info: Some(&infos[0]),
+                        index: files[0].index,
+                        total,
+                        started,
+                        frame: None,
+                        frames: 0,
+                        stream: None,
+                    };
+                    let output = match Self::sanitize_output(
+                        &self.logger,
+                        &self.sanitized_outputs,
+                        rendition.pattern.render(&context),
+                        cmd_args.sanitize,
+                        thread,
+                        verbose,
+                    ) {
+                        Ok(output) => output,
+                        Err(err) => {
+                            fail(err);
+                            continue;
+                        }
+                    };
+
+                    if cmd_args.skip_existing_newer
+                        && paths.iter().all(|path| Self::is_newer(&output, path))
+                    {
+                        self.logger.log_info(
+                            format!("{} is up to date, skipping {}", output.display(), first.display()),
+                            thread,
+                            verbose,
+                        );
+                        self.up_to_date_paths
+                            .lock()
+                            .unwrap()
+                            .push(first.display().to_string());
+                        self.report.record(first, &[&output], Outcome::UpToDate, None);
+                        Self::advance_progress(
+                            &self.progress,
+                            #[cfg(feature = "ui")]
+                            &app_handle,
+                        );
+                        continue;
+                    }
+
+                    // outputs older than one of the files are re-created with --skip-existing-newer
+                    let overwrite = cmd_args.overwrite || cmd_args.skip_existing_newer;
+                    if output.exists() && !overwrite {
+                        fail(format!("{} already exists", output.display()));
+                        continue;
+                    }
+                    if let Some(parent) = output.parent() {
+                        if let Err(err) = create_dir_all(parent) {
+                            fail(format!("Could not create {}: {err}", parent.display()));
+                            continue;
+                        }
+                    }
+
+                    let compatible = match concat_args.reencode {
+                        true => Err("--reencode was passed".to_string()),
+                        false => concat::check_compatible(&paths, &infos),
+                    };
+                    let list = std::env::temp_dir()
+                        .join(format!("ffzap-{}-{thread}-concat.txt", std::process::id()));
+                    let command = match &compatible {
+                        Ok(_) => concat::write_list(&paths, &list)
+                            .map(|_| concat::copy_command(&list, &output))
+                            .map_err(|err| format!("Could not write {}: {err}", list.display())),
+                        Err(reason) => {
+                            self.logger.log_info(
+                                format!("Re-encoding, as the files can't be joined as they are: {reason}"),
+                                thread,
+                                verbose,
+                            );
+                            concat::reencode_command(
+                                &paths,
+                                &infos,
+                                &rendition.split_options(),
+                                &output,
+                            )
+                        }
+                    };
+                    let mut command = match command {
+                        Ok(command) => command,
+                        Err(err) => {
+                            fail(err);
+                            continue;
+                        }
+                    };
+
+                    command.stdout(Stdio::null());
+                    command.stderr(Stdio::piped());
+                    #[cfg(target_os = "windows")]
+                    command.creation_flags(0x08000000); // don't show cmd windows on Windows
+                    if overwrite {
+                        command.arg("-y");
+                    }
+
+                    let result = command.output();
+                    let _ = remove_file(&list);
+
+                    match result {
+                        Ok(result) if result.status.success() => {
+                            let outcome = match compatible {
+                                Ok(_) => Outcome::Remuxed,
+                                Err(_) => Outcome::Transcoded,
+                            };
+                            let joined = paths
+                                .iter()
+                                .map(|path| path.display().to_string())
+                                .collect::<Vec<_>>()
+                                .join(", ");
+                            self.report.record(
+                                first,
+                                &[&output],
+                                outcome,
+                                Some(format!("joined {joined}")),
+                            );
+                            // the newest file ends the recording, and the output stays up to date
+                            // with --skip-existing-newer that way
+                            let newest = paths
+                                .iter()
+                                .max_by_key(|path| path.metadata().and_then(|m| m.modified()).ok())
+                                .unwrap_or(&paths[0]);
+                            Self::finish(
+                                &self.logger,
+                                &self.progress,
+                                #[cfg(feature = "ui")]
+                                &app_handle,
+                                preserve,
+                                newest,
+                                &paths,
+                                std::slice::from_ref(&output),
+                                cmd_args.delete,
+                                cmd_args.move_processed.as_deref(),
+                                thread,
+                                verbose,
+                            );
+                        }
+                        Ok(result) => fail(String::from_utf8_lossy(&result.stderr).trim().to_string()),
+                        Err(_) => fail("There was an error running ffmpeg. Please check if it's correctly installed and working as intended.".to_string()),
+                    }
+                });
+            }
+        });
+        concurrency.finish();
+
+        self.progress.finish();
+    }
+
+    /// Processes paths from `queue` until it is closed and empty. Paths can be pushed to
+    /// the queue from other threads while it's being processed.
+    pub fn process_queue(
+        &self,
+        paths: Arc<Queue>,
+        cmd_args: &CmdArgs,
+        #[cfg(feature = "ui")] app_handle: AppHandle,
+    ) {
+        let renditions = match Rendition::from_args(cmd_args) {
+            Ok(renditions) => Arc::new(renditions),
+            Err(err) => {
+                self.logger
+                    .log_main_error(format!("Invalid output pattern: {err}"), true);
+                return;
+            }
+        };
+        let loudnorm = match cmd_args.loudnorm.as_deref().map(Loudnorm::parse) {
+            Some(Ok(loudnorm)) => Some(loudnorm),
+            Some(Err(err)) => {
+                self.logger
+                    .log_main_error(format!("Invalid loudnorm targets: {err}"), true);
+                return;
+            }
+            None => None,
+        };
+        let conditions = |conditions: &Option<String>| match conditions {
+            Some(conditions) => {
+                Filter::parse_conditions(conditions).map(|filter| Some(Arc::new(filter)))
+            }
+            None => Ok(None),
+        };
+        let (skip_if, remux_if) = match (
+            conditions(&cmd_args.skip_if),
+            conditions(&cmd_args.remux_if),
+        ) {
+            (Ok(skip_if), Ok(remux_if)) => (skip_if, remux_if),
+            (Err(err), _) | (_, Err(err)) => {
+                self.logger
+                    .log_main_error(format!("Invalid conditions: {err}"), true);
+                return;
+            }
+        };
+        let quality_fallback = match &cmd_args.quality_fallback {
+            Some(name) => match Config::load().and_then(|config| {
+                let preset = config.preset(name)?;
+                preset
+                    .ffmpeg_options
+                    .clone()
+                    .ok_or(format!("Preset '{name}' has no ffmpeg_options"))
+            }) {
+                Ok(options) => Some(options),
+                Err(err) => {
+                    self.logger
+                        .log_main_error(format!("Invalid quality fallback: {err}"), true);
+                    return;
+                }
+            },
+            None => None,
+        };
+        let thumbs = match &cmd_args.command {
+            Some(args::Command::Thumbs(thumbs_args)) => Some(Arc::new(thumbs_args.clone())),
+            _ => None,
+        };
+        let extract = match &cmd_args.command {
+            Some(args::Command::Extract(extract_args)) => Some(Arc::new(extract_args.clone())),
+            _ => None,
+        };
+        if (thumbs.is_some() || extract.is_some()) && !cmd_args.extra_output.is_empty() {
+            self.logger.log_main_error(
+                "--extra-output can't be used with thumbs or extract".to_string(),
+                true,
+            );
+            return;
+        }
+        let started = Local::now();
+        let fingerprints = Arc::new(cmd_args.skip_unchanged.then(Fingerprints::load));
         let mut thread_handles = vec![];
 
         self.progress.start_stick(1000);
 
-        for thread in 0..thread_count {
+        // segmented files are spread over all threads, so only one file is encoded at a time
+        let concurrency = match cmd_args.segment_length {
+            Some(_) => Concurrency::fixed(1),
+            None => Concurrency::start(
+                cmd_args.thread_count,
+                cmd_args.min_threads,
+                cmd_args.max_threads,
+                Arc::clone(&self.logger),
+                cmd_args.verbose,
+            ),
+        };
+
+        for thread in 0..concurrency.workers {
+            let limiter = Arc::clone(&concurrency.limiter);
             let paths = Arc::clone(&paths);
             let failed_paths = Arc::clone(&self.failed_paths);
+            let up_to_date_paths = Arc::clone(&self.up_to_date_paths);
             let progress = Arc::clone(&self.progress);
             let logger = Arc::clone(&self.logger);
-            let ffmpeg_options = ffmpeg_options.clone();
-            let output_pattern = output_pattern.clone();
+            let fingerprints = Arc::clone(&fingerprints);
+            let renditions = Arc::clone(&renditions);
+            let ext_map = cmd_args.ext_map.clone();
+            let overwrite = cmd_args.overwrite;
+            let verbose = cmd_args.verbose;
+            let delete = cmd_args.delete;
+            let move_processed = cmd_args.move_processed.clone();
+            let skip_existing_newer = cmd_args.skip_existing_newer;
+            let sanitize = cmd_args.sanitize;
+            let two_pass = cmd_args.two_pass;
+            let target_size = cmd_args.target_size;
+            let skip_if = skip_if.clone();
+            let remux_if = remux_if.clone();
+            let thumbs: Option<Arc<ThumbsArgs>> = thumbs.clone();
+            let extract: Option<Arc<ExtractArgs>> = extract.clone();
+            let quality_check = cmd_args.quality_check
+                || cmd_args.min_ssim.is_some()
+                || cmd_args.min_psnr.is_some();
+            let min_ssim = cmd_args.min_ssim;
+            let min_psnr = cmd_args.min_psnr;
+            let quality_fallback = quality_fallback.clone();
+            let keep_smaller = cmd_args.keep_smaller;
+            let copy_original = cmd_args.copy_original;
+            let preserve = Preserve::from_args(cmd_args);
+            let segment_length = cmd_args.segment_length;
+            let segment_threads = cmd_args.thread_count.max(cmd_args.max_threads) as usize;
+            let report = Arc::clone(&self.report);
+            let written_outputs = Arc::clone(&self.written_outputs);
+            let sanitized_outputs = Arc::clone(&self.sanitized_outputs);
             #[cfg(feature = "ui")]
             let app_handle = app_handle.clone();
 
             let handle = thread::spawn(move || loop {
-                let path_to_process = {
-                    let mut queue = paths.lock().unwrap();
-                    queue.pop()
-                };
+                let _slot = limiter.acquire();
+                let path_to_process = paths.pop();
 
                 match path_to_process {
-                    Some(path) => {
-                        let path = Path::new(&path);
+                    Some(file) => {
+                        let path = file.path.as_path();
 
                         if !path.is_file() {
                             logger.log_error(
@@ -71,98 +617,615 @@ impl Processor {
                                 thread,
                                 verbose,
                             );
+                            report.record(
+                                path,
+                                &[] as &[PathBuf],
+                                Outcome::Skipped,
+                                Some("not a file".to_string()),
+                            );
                             continue;
                         }
 
                         logger.log_info(format!("Processing {}", path.display()), thread, verbose);
 
-                        let split_options = match &ffmpeg_options {
-                            Some(options) => options.split(' ').collect::<Vec<&str>>(),
-                            None => vec![],
+                        let renditions = match Rendition::for_file(
+                            &renditions,
+                            &file,
+                            ext_map.as_deref(),
+                        ) {
+                            Ok(renditions) => renditions,
+                            Err(err) => {
+                                logger.log_error(
+                                    format!("Invalid output pattern for {}: {err}. Continuing with next task if there's more to do...", path.display()),
+                                    thread,
+                                    verbose,
+                                );
+                                failed_paths
+                                    .lock()
+                                    .unwrap()
+                                    .push(path.display().to_string());
+                                continue;
+                            }
+                        };
+
+                        let needs_metadata = target_size.is_some()
+                            || loudnorm.is_some()
+                            || segment_length.is_some()
+                            || thumbs.is_some()
+                            || extract.is_some()
+                            || skip_if.is_some()
+                            || remux_if.is_some()
+                            || renditions
+                                .iter()
+                                .any(|rendition| rendition.pattern.needs_metadata());
+
+                        let media_info = if needs_metadata {
+                            match MediaInfo::probe(path) {
+                                Ok(media_info) => Some(media_info),
+                                Err(err) => {
+                                    logger.log_error(
+                                        format!("Could not read metadata of {}: {err}. Continuing with next task if there's more to do...", path.display()),
+                                        thread,
+                                        verbose,
+                                    );
+                                    failed_paths
+                                        .lock()
+                                        .unwrap()
+                                        .push(path.display().to_string());
+                                    continue;
+                                }
+                            }
+                        } else {
+                            None
                         };
 
-                        let final_file_name = Self::build_output_path(&path, &output_pattern);
+                        let matches =
+                            |conditions: &Option<Arc<Filter>>| match (conditions, &media_info) {
+                                (Some(conditions), Some(media_info)) => {
+                                    conditions.evaluate(media_info).is_ok()
+                                }
+                                _ => false,
+                            };
 
-                        if Path::new(&final_file_name).exists() && !overwrite {
-                            logger.log_error(
-                                format!("File {final_file_name} already exists and --overwrite is set to false. Continuing with next task if there is more to do..."),
+                        if matches(&skip_if) {
+                            logger.log_info(
+                                format!("{} matches --skip-if, skipping it", path.display()),
                                 thread,
-                                verbose
+                                verbose,
+                            );
+                            report.record(path, &[] as &[PathBuf], Outcome::Skipped, None);
+
+                            Self::advance_progress(
+                                &progress,
+                                #[cfg(feature = "ui")]
+                                &app_handle,
                             );
-                            failed_paths.lock().unwrap().push(final_file_name);
                             continue;
                         }
 
-                        let final_path_parent = Path::new(&final_file_name).parent().unwrap();
-
-                        if !final_path_parent.exists() {
-                            match create_dir_all(final_path_parent) {
-                                Ok(_) => {}
+                        // copying streams can't be combined with filters or bitrates, so remuxed
+                        // files, images and extracted streams skip two-pass encoding,
+                        // --target-size and --loudnorm
+                        let derives = thumbs.is_some() || extract.is_some();
+                        let remux = !derives && matches(&remux_if);
+                        let encodes_media = !remux && !derives;
+                        let mut renditions = renditions;
+                        if remux {
+                            logger.log_info(
+                                format!(
+                                    "{} matches --remux-if, copying its streams",
+                                    path.display()
+                                ),
+                                thread,
+                                verbose,
+                            );
+                            renditions[0].ffmpeg_options = Some(REMUX_OPTIONS.to_string());
+                        }
+                        if let (Some(thumbs), Some(media_info)) = (&thumbs, &media_info) {
+                            match thumbs::image_options(thumbs, media_info) {
+                                Ok(image_options) => {
+                                    renditions =
+                                        Rendition::thumbnails(&renditions[0], image_options)
+                                }
                                 Err(err) => {
                                     logger.log_error(
-                                        format!(
-                                            "Could not create directory structure for file {}",
-                                            final_file_name
-                                        ),
+                                        format!("Could not create thumbnails of {}: {err}. Continuing with next task if there's more to do...", path.display()),
                                         thread,
                                         verbose,
                                     );
-                                    logger.log_error(format!("{}", err), thread, verbose);
+                                    failed_paths
+                                        .lock()
+                                        .unwrap()
+                                        .push(path.display().to_string());
+                                    continue;
                                 }
                             }
                         }
+                        if let (Some(extract), Some(media_info)) = (&extract, &media_info) {
+                            let stream_options = extract::stream_options(extract, media_info);
+                            if stream_options.is_empty() {
+                                logger.log_info(
+                                    format!(
+                                        "{} has no streams to extract, skipping it",
+                                        path.display()
+                                    ),
+                                    thread,
+                                    verbose,
+                                );
+                                report.record(
+                                    path,
+                                    &[] as &[PathBuf],
+                                    Outcome::Skipped,
+                                    Some("no streams to extract".to_string()),
+                                );
 
-                        let mut command = Command::new("ffmpeg");
-                        command.arg("-i").arg(path.to_str().unwrap());
-                        command.args(split_options);
-                        command.arg(&final_file_name);
-                        command.stdout(Stdio::null());
-                        command.stderr(Stdio::piped());
-                        #[cfg(target_os = "windows")]
-                        command.creation_flags(0x08000000); // don't show cmd windows on Windows
+                                Self::advance_progress(
+                                    &progress,
+                                    #[cfg(feature = "ui")]
+                                    &app_handle,
+                                );
+                                continue;
+                            }
+                            renditions = Rendition::streams(&renditions[0], stream_options);
+                        }
+                        let two_pass = two_pass && encodes_media;
+                        let target_size = target_size.filter(|_| encodes_media);
+                        let loudnorm = loudnorm.filter(|_| encodes_media);
+                        let mut segment_length = segment_length.filter(|_| encodes_media);
+                        // chunks are cut from the video, so audio only files are encoded as a whole
+                        let video = media_info
+                            .as_ref()
+                            .and_then(|media_info| media_info.first_stream("video"));
+                        if let (Some(_), None) = (segment_length, video) {
+                            logger.log_info(
+                                format!(
+                                    "{} has no video, encoding it in one piece",
+                                    path.display()
+                                ),
+                                thread,
+                                verbose,
+                            );
+                            segment_length = None;
+                        }
 
-                        if overwrite {
-                            command.arg("-y");
+                        if let (Some(_), Err(err)) = (
+                            segment_length,
+                            segment::check_options(&renditions[0].split_options()),
+                        ) {
+                            logger.log_error(
+                                format!("Can't encode {} in segments: {err}. Continuing with next task if there's more to do...", path.display()),
+                                thread,
+                                verbose,
+                            );
+                            failed_paths
+                                .lock()
+                                .unwrap()
+                                .push(path.display().to_string());
+                            continue;
                         }
 
-                        if let Ok(output) = command.output() {
-                            if output.status.success() {
-                                logger.log_info(
-                                    format!("Success, saving to {final_file_name}"),
+                        let mut context = TemplateContext {
+                            path,
+                            base_dir: file.base_dir.as_deref(),
+                            media_info: media_info.as_ref(),
+                            index: file.index,
+                            total: progress.len() as usize,
+                            started,
+                            frame: None,
+                            frames: renditions.len(),
+                            stream: None,
+                        };
+
+                        let outputs = match renditions
+                            .iter()
+                            .map(|rendition| {
+                                context.frame = rendition.frame;
+                                context.stream = rendition.stream.and_then(|index| {
+                                    media_info
+                                        .as_ref()?
+                                        .streams
+                                        .iter()
+                                        .find(|stream| stream.index == index)
+                                });
+                                Self::sanitize_output(
+                                    &logger,
+                                    &sanitized_outputs,
+                                    rendition.pattern.render(&context),
+                                    sanitize,
+                                    thread,
+                                    verbose,
+                                )
+                            })
+                            .collect::<Result<Vec<_>, _>>()
+                        {
+                            Ok(outputs) => outputs,
+                            Err(err) => {
+                                logger.log_error(
+                                    format!("Can't process {}: {err}. Continuing with next task if there's more to do...", path.display()),
                                     thread,
                                     verbose,
                                 );
+                                failed_paths
+                                    .lock()
+                                    .unwrap()
+                                    .push(path.display().to_string());
+                                continue;
+                            }
+                        };
 
-                                if delete {
-                                    match remove_file(path) {
-                                        Ok(_) => logger.log_info(
-                                            format!("Removed {}", path.display()),
+                        if let Some(output) = outputs
+                            .iter()
+                            .enumerate()
+                            .find(|(i, output)| outputs[..*i].contains(output))
+                            .map(|(_, output)| output)
+                        {
+                            logger.log_error(
+                                format!("{} would be written more than once, the outputs of {} need different names (e.g. with {{{{frame}}}} for thumbs or {{{{stream_index}}}} for extract). Continuing with next task if there's more to do...", output.display(), path.display()),
+                                thread,
+                                verbose,
+                            );
+                            failed_paths
+                                .lock()
+                                .unwrap()
+                                .push(path.display().to_string());
+                            continue;
+                        }
+                        let outputs_display = Self::display_outputs(&outputs);
+
+                        let fingerprint = match fingerprints.as_ref() {
+                            Some(_) => match Fingerprints::compute(
+                                path,
+                                &Self::fingerprint_settings(
+                                    &renditions,
+                                    &outputs,
+                                    two_pass,
+                                    target_size,
+                                    loudnorm,
+                                    segment_length,
+                                    quality_fallback
+                                        .as_ref()
+                                        .filter(|_| quality_check)
+                                        .map(|fallback| (fallback, min_ssim, min_psnr)),
+                                ),
+                            ) {
+                                Ok(fingerprint) => Some(fingerprint),
+                                Err(err) => {
+                                    logger.log_error(
+                                        format!("Could not fingerprint {}: {err}", path.display()),
+                                        thread,
+                                        verbose,
+                                    );
+                                    None
+                                }
+                            },
+                            None => None,
+                        };
+
+                        // a file is only up to date if all of its renditions are
+                        let is_up_to_date = outputs.iter().all(|output| {
+                            output.exists()
+                                && match (fingerprints.as_ref(), &fingerprint) {
+                                    (Some(fingerprints), Some(fingerprint)) => {
+                                        fingerprints.matches(output, fingerprint)
+                                    }
+                                    _ => skip_existing_newer && Self::is_newer(output, path),
+                                }
+                        });
+
+                        if is_up_to_date {
+                            logger.log_info(
+                                format!(
+                                    "{} is up to date, skipping {}",
+                                    outputs_display,
+                                    path.display()
+                                ),
+                                thread,
+                                verbose,
+                            );
+                            up_to_date_paths
+                                .lock()
+                                .unwrap()
+                                .push(path.display().to_string());
+                            report.record(path, &outputs, Outcome::UpToDate, None);
+
+                            Self::advance_progress(
+                                &progress,
+                                #[cfg(feature = "ui")]
+                                &app_handle,
+                            );
+                            continue;
+                        }
+
+                        // outdated outputs are re-created when skipping up to date files, but files
+                        // ffzap didn't create are only replaced with --overwrite
+                        let is_replaceable = |output: &Path| {
+                            overwrite
+                                || fingerprints
+                                    .as_ref()
+                                    .as_ref()
+                                    .is_some_and(|fingerprints| fingerprints.contains(output))
+                                || (skip_existing_newer && !Self::is_newer(output, path))
+                        };
+
+                        let existing_output = outputs
+                            .iter()
+                            .find(|output| output.exists() && !is_replaceable(output));
+                        if let Some(existing_output) = existing_output {
+                            logger.log_error(
+                                format!("File {} already exists and --overwrite is set to false. Continuing with next task if there is more to do...", existing_output.display()),
+                                thread,
+                                verbose
+                            );
+                            report.record(
+                                path,
+                                &outputs,
+                                Outcome::Failed,
+                                Some(format!("{} already exists", existing_output.display())),
+                            );
+                            failed_paths
+                                .lock()
+                                .unwrap()
+                                .push(path.display().to_string());
+                            continue;
+                        }
+                        let overwrite = overwrite || outputs.iter().any(|output| output.exists());
+
+                        for final_file_name in &outputs {
+                            let final_path_parent =
+                                final_file_name.parent().unwrap_or(Path::new(""));
+
+                            if !final_path_parent.exists() {
+                                match create_dir_all(final_path_parent) {
+                                    Ok(_) => {}
+                                    Err(err) => {
+                                        logger.log_error(
+                                            format!(
+                                                "Could not create directory structure for file {}",
+                                                final_file_name.display()
+                                            ),
+                                            thread,
+                                            verbose,
+                                        );
+                                        logger.log_error(format!("{}", err), thread, verbose);
+                                    }
+                                }
+                            }
+                        }
+                        written_outputs
+                            .lock()
+                            .unwrap()
+                            .extend(outputs.iter().map(|output| crate::resolve_output(output)));
+
+                        let video_bitrate = match (target_size, &media_info) {
+                            (Some(target_size), Some(media_info)) => {
+                                match target_size::video_bitrate(
+                                    media_info,
+                                    target_size,
+                                    renditions[0].ffmpeg_options.as_deref(),
+                                ) {
+                                    Ok(bitrate) => Some(bitrate),
+                                    Err(err) => {
+                                        logger.log_error(
+                                            format!("Can't encode {} to {target_size} bytes: {err}. Continuing with next task if there's more to do...", path.display()),
                                             thread,
                                             verbose,
-                                        ),
-                                        Err(err) => match err.kind() {
-                                            ErrorKind::PermissionDenied => logger.log_error(
-                                                format!("Permission denied when trying to delete file {}", path.display()),
+                                        );
+                                        failed_paths
+                                            .lock()
+                                            .unwrap()
+                                            .push(path.display().to_string());
+                                        continue;
+                                    }
+                                }
+                            }
+                            _ => None,
+                        };
+
+                        let mut loudnorm_args = vec![];
+                        let audio = media_info
+                            .as_ref()
+                            .and_then(|media_info| media_info.first_stream("audio"));
+                        if let (Some(_), None) = (&loudnorm, audio) {
+                            logger.log_info(
+                                format!("{} has no audio, not normalizing it", path.display()),
+                                thread,
+                                verbose,
+                            );
+                        }
+                        if let (Some(loudnorm), Some(audio)) = (&loudnorm, audio) {
+                            logger.log_info(
+                                format!("Measuring loudness of {}", path.display()),
+                                thread,
+                                verbose,
+                            );
+                            match loudnorm.measure(path) {
+                                Ok(measurement) if measurement.is_silent() => logger.log_info(
+                                    format!("{} is silent, not normalizing it", path.display()),
+                                    thread,
+                                    verbose,
+                                ),
+                                Ok(measurement) => {
+                                    let ffmpeg_options = renditions
+                                        .iter()
+                                        .flat_map(Rendition::split_options)
+                                        .collect::<Vec<_>>();
+                                    loudnorm_args = loudnorm.encode_args(
+                                        &measurement,
+                                        audio.sample_rate.as_deref(),
+                                        &ffmpeg_options,
+                                    );
+                                }
+                                Err(err) => {
+                                    logger.log_error(
+                                        format!("Could not measure the loudness of {}: {err}. Continuing with next task if there's more to do...", path.display()),
+                                        thread,
+                                        verbose,
+                                    );
+                                    failed_paths
+                                        .lock()
+                                        .unwrap()
+                                        .push(path.display().to_string());
+                                    continue;
+                                }
+                            }
+                        }
+
+                        let (result, extra_args) = match Self::encode(
+                            &logger,
+                            path,
+                            &renditions,
+                            &outputs,
+                            loudnorm_args,
+                            two_pass,
+                            target_size.zip(video_bitrate),
+                            segment_length.map(|length| (length, segment_threads)),
+                            overwrite,
+                            thread,
+                            verbose,
+                        ) {
+                            Ok(encoded) => encoded,
+                            Err(err) => {
+                                logger.log_error(
+                                    format!(
+                                        "{err}. Continuing with next task if there's more to do..."
+                                    ),
+                                    thread,
+                                    verbose,
+                                );
+                                failed_paths
+                                    .lock()
+                                    .unwrap()
+                                    .push(path.display().to_string());
+                                continue;
+                            }
+                        };
+                        let succeeded = result.as_ref().is_ok_and(|output| output.status.success());
+
+                        // remuxed files are identical to their source and images can't be compared
+                        let mut scores_detail = None;
+                        if quality_check && succeeded && encodes_media {
+                            match Self::check_quality(
+                                &logger,
+                                path,
+                                &renditions,
+                                &outputs,
+                                &extra_args,
+                                quality_fallback.as_deref(),
+                                (min_ssim, min_psnr),
+                                thread,
+                                verbose,
+                            ) {
+                                Ok(detail) => scores_detail = detail,
+                                Err(reason) => {
+                                    logger.log_error(
+                                        format!("Quality check of {} failed: {reason}. Continuing with next task if there's more to do...", outputs[0].display()),
+                                        thread,
+                                        verbose,
+                                    );
+                                    // rejected outputs must not be mistaken for finished ones
+                                    Self::remove_outputs(&logger, &outputs, thread, verbose);
+                                    report.record(
+                                        path,
+                                        &[] as &[PathBuf],
+                                        Outcome::Failed,
+                                        Some(reason),
+                                    );
+                                    failed_paths
+                                        .lock()
+                                        .unwrap()
+                                        .push(path.display().to_string());
+                                    continue;
+                                }
+                            }
+                        }
+
+                        if let Ok(output) = result {
+                            if output.status.success() {
+                                let mut outcome = match remux {
+                                    true => Outcome::Remuxed,
+                                    false => Outcome::Transcoded,
+                                };
+                                let mut detail = scores_detail;
+                                let mut recorded_outputs = outputs.clone();
+
+                                // remuxing isn't meant to save space and images are much smaller
+                                // anyway, so they are always kept
+                                if let (Some(margin), true) = (keep_smaller, encodes_media) {
+                                    if let Some(sizes) = Self::discard_if_not_smaller(
+                                        &logger,
+                                        path,
+                                        &outputs[0],
+                                        margin,
+                                        thread,
+                                        verbose,
+                                    ) {
+                                        recorded_outputs.remove(0);
+                                        if copy_original {
+                                            if let Some(copy_path) = Self::copy_original(
+                                                &logger,
+                                                path,
+                                                &outputs,
+                                                is_replaceable,
+                                                &written_outputs,
                                                 thread,
                                                 verbose,
-                                            ),
-                                            _ => logger.log_error(
-                                                format!("An unknown error occurred when trying to delete file {}", path.display()),
-                                                thread,
-                                                verbose
-                                            )
-                                        },
+                                            ) {
+                                                recorded_outputs.insert(0, copy_path);
+                                            }
+                                        }
+
+                                        detail = Some(match detail {
+                                            Some(scores) => format!("{sizes}, {scores}"),
+                                            None => sizes,
+                                        });
+                                        outcome = Outcome::NotBeneficial;
                                     }
                                 }
+                                report.record(path, &recorded_outputs, outcome, detail);
 
-                                progress.inc(1);
-                                #[cfg(feature = "ui")]
-                                {
-                                    use tauri::Emitter;
+                                // the source is all that's left of a file that wasn't worth it
+                                if outcome == Outcome::NotBeneficial {
+                                    Self::advance_progress(
+                                        &progress,
+                                        #[cfg(feature = "ui")]
+                                        &app_handle,
+                                    );
+                                    continue;
+                                }
 
-                                    let done = progress.value();
-                                    let _ = app_handle.emit("progress-update", done);
+                                if let (Some(fingerprints), Some(fingerprint)) =
+                                    (fingerprints.as_ref(), fingerprint)
+                                {
+                                    for final_file_name in &outputs {
+                                        if let Err(err) = fingerprints
+                                            .record(final_file_name, fingerprint.clone())
+                                        {
+                                            logger.log_error(
+                                                format!(
+                                                    "Could not record fingerprint for {}: {err}",
+                                                    final_file_name.display()
+                                                ),
+                                                thread,
+                                                verbose,
+                                            );
+                                        }
+                                    }
                                 }
+
+                                Self::finish(
+                                    &logger,
+                                    &progress,
+                                    #[cfg(feature = "ui")]
+                                    &app_handle,
+                                    preserve,
+                                    path,
+                                    &[path],
+                                    &outputs,
+                                    delete,
+                                    move_processed.as_deref(),
+                                    thread,
+                                    verbose,
+                                );
                             } else {
                                 logger.log_error(
                                     format!(
@@ -203,6 +1266,17 @@ impl Processor {
                             }
 
                             eprintln!("{}", line);
+
+                            report.record(
+                                path,
+                                &outputs,
+                                Outcome::Failed,
+                                Some("ffmpeg could not be run".to_string()),
+                            );
+                            failed_paths
+                                .lock()
+                                .unwrap()
+                                .push(path.display().to_string());
                         }
                     }
                     None => {
@@ -217,6 +1291,15 @@ impl Processor {
         for handle in thread_handles {
             handle.join().unwrap();
         }
+        concurrency.finish();
+
+        // most failures are only collected as paths, the reasons are in the log
+        for path in self.failed_paths.lock().unwrap().iter() {
+            if !self.report.contains(Path::new(path)) {
+                self.report
+                    .record(Path::new(path), &[] as &[PathBuf], Outcome::Failed, None);
+            }
+        }
 
         self.progress.finish();
     }
@@ -225,25 +1308,741 @@ impl Processor {
         self.failed_paths.lock().unwrap().clone()
     }
 
-    fn build_output_path(path: &Path, output_pattern: &str) -> String {
-        let mut final_file_name =
-            output_pattern.replace("{{ext}}", path.extension().unwrap().to_str().unwrap());
-        final_file_name =
-            final_file_name.replace("{{name}}", &path.file_stem().unwrap().to_str().unwrap());
-        final_file_name = final_file_name.replace(
-            "{{dir}}",
-            &path.parent().unwrap_or(Path::new("")).to_str().unwrap(),
+    pub fn get_up_to_date_paths(&self) -> Vec<String> {
+        self.up_to_date_paths.lock().unwrap().clone()
+    }
+
+    pub fn get_report(&self) -> &Report {
+        &self.report
+    }
+
+    /// Every output written so far with symlinks resolved, shared so `ffzap watch` can tell
+    /// them apart from new files while processing continues
+    pub fn get_written_outputs(&self) -> Arc<Mutex<HashSet<PathBuf>>> {
+        Arc::clone(&self.written_outputs)
+    }
+
+    /// Everything that changes the outputs of a file, so `--skip-unchanged` re-creates them if
+    /// any of it does
+    fn fingerprint_settings(
+        renditions: &[Rendition],
+        outputs: &[PathBuf],
+        two_pass: bool,
+        target_size: Option<u64>,
+        loudnorm: Option<Loudnorm>,
+        segment_length: Option<u64>,
+        quality_fallback: Option<(&String, Option<f64>, Option<f64>)>,
+    ) -> String {
+        let mut settings = vec![];
+        for (rendition, output) in renditions.iter().zip(outputs) {
+            settings.push(format!(
+                "{} -> {}",
+                rendition.ffmpeg_options.as_deref().unwrap_or(""),
+                crate::resolve_output(output).display()
+            ));
+        }
+        settings.push(format!(
+            "two_pass={two_pass} target_size={target_size:?} loudnorm={loudnorm:?} segment_length={segment_length:?} quality_fallback={quality_fallback:?}"
+        ));
+
+        settings.join("\n")
+    }
+
+    /// Counts a file as done and tells the ui about it
+    fn advance_progress(progress: &Progress, #[cfg(feature = "ui")] app_handle: &AppHandle) {
+        progress.inc(1);
+        #[cfg(feature = "ui")]
+        {
+            use tauri::Emitter;
+
+            let done = progress.value();
+            let _ = app_handle.emit("progress-update", done);
+        }
+    }
+
+    /// Makes `output` valid on the file system of `--sanitize`, with a warning if it changed.
+    /// Fails if another output already became the same path, e.g. `a?.mkv` and `a*.mkv`.
+    fn sanitize_output(
+        logger: &Logger,
+        sanitized_outputs: &Mutex<HashMap<PathBuf, PathBuf>>,
+        output: PathBuf,
+        sanitize: Option<Sanitize>,
+        thread: u16,
+        verbose: bool,
+    ) -> Result<PathBuf, String> {
+        let Some(sanitize) = sanitize else {
+            return Ok(output);
+        };
+
+        let sanitized = sanitize.sanitize_path(&output);
+        // valid outputs are remembered as well, `a_.mkv` is taken once `a?.mkv` became it
+        let mut sanitized_outputs = sanitized_outputs.lock().unwrap();
+        match sanitized_outputs.get(&sanitized) {
+            Some(other) if *other != output => {
+                return Err(format!(
+                    "{} and {} would both be written to {}",
+                    other.display(),
+                    output.display(),
+                    sanitized.display()
+                ));
+            }
+            Some(_) => {}
+            None => {
+                sanitized_outputs.insert(sanitized.clone(), output.clone());
+            }
+        }
+
+        if sanitized != output {
+            logger.log_warning(
+                format!(
+                    "Output path {} is not valid on the target file system, using {} instead",
+                    output.display(),
+                    sanitized.display()
+                ),
+                thread,
+                verbose,
+            );
+        }
+
+        Ok(sanitized)
+    }
+
+    /// Copies the metadata of `source` the job asks for to every output
+    fn preserve_metadata(
+        logger: &Logger,
+        preserve: Preserve,
+        source: &Path,
+        outputs: &[PathBuf],
+        thread: u16,
+        verbose: bool,
+    ) {
+        type CopyMetadata = fn(&Path, &Path) -> io::Result<()>;
+        // times go last, as copying the rest would change them
+        let metadata_to_copy: [(bool, &str, CopyMetadata); 3] = [
+            (
+                preserve.xattrs,
+                "extended attributes",
+                preserve::copy_xattrs,
+            ),
+            (preserve.owner, "owner", preserve::copy_owner),
+            (preserve.times, "times", preserve::copy_times),
+        ];
+
+        for output in outputs {
+            for (enabled, what, copy_metadata) in metadata_to_copy {
+                if !enabled {
+                    continue;
+                }
+                if let Err(err) = copy_metadata(source, output) {
+                    logger.log_error(
+                        format!(
+                            "Could not copy the {what} of {} to {}: {err}",
+                            source.display(),
+                            output.display()
+                        ),
+                        thread,
+                        verbose,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Removes outputs that were written but are not wanted
+    fn remove_outputs(logger: &Logger, outputs: &[PathBuf], thread: u16, verbose: bool) {
+        for output in outputs {
+            match remove_file(output) {
+                Ok(_) => logger.log_info(format!("Removed {}", output.display()), thread, verbose),
+                Err(err) => logger.log_error(
+                    format!("Could not remove {}: {err}", output.display()),
+                    thread,
+                    verbose,
+                ),
+            }
+        }
+    }
+
+    /// Finishes a file or group that was processed successfully: copies the metadata of
+    /// `metadata_source` to the outputs, deletes or moves the `sources` if the job asks for it
+    /// and counts it as done
+    #[allow(clippy::too_many_arguments)]
+    fn finish(
+        logger: &Logger,
+        progress: &Progress,
+        #[cfg(feature = "ui")] app_handle: &AppHandle,
+        preserve: Preserve,
+        metadata_source: &Path,
+        sources: &[impl AsRef<Path>],
+        outputs: &[PathBuf],
+        delete: bool,
+        move_processed: Option<&Path>,
+        thread: u16,
+        verbose: bool,
+    ) {
+        logger.log_info(
+            format!("Success, saving to {}", Self::display_outputs(outputs)),
+            thread,
+            verbose,
         );
-        final_file_name = final_file_name.replace(
-            "{{parent}}",
-            &path
-                .parent()
-                .unwrap_or(Path::new(""))
-                .file_name()
-                .unwrap_or(OsStr::new(""))
-                .to_str()
-                .unwrap_or(""),
+
+        Self::preserve_metadata(logger, preserve, metadata_source, outputs, thread, verbose);
+
+        for source in sources {
+            Self::finish_source(
+                logger,
+                source.as_ref(),
+                delete,
+                move_processed,
+                thread,
+                verbose,
+            );
+        }
+
+        Self::advance_progress(
+            progress,
+            #[cfg(feature = "ui")]
+            app_handle,
         );
-        final_file_name
+    }
+
+    /// Deletes or moves `path` after it was processed, if the job asks for it
+    fn finish_source(
+        logger: &Logger,
+        path: &Path,
+        delete: bool,
+        move_processed: Option<&Path>,
+        thread: u16,
+        verbose: bool,
+    ) {
+        if delete {
+            match remove_file(path) {
+                Ok(_) => logger.log_info(format!("Removed {}", path.display()), thread, verbose),
+                Err(err) => match err.kind() {
+                    ErrorKind::PermissionDenied => logger.log_error(
+                        format!(
+                            "Permission denied when trying to delete file {}",
+                            path.display()
+                        ),
+                        thread,
+                        verbose,
+                    ),
+                    _ => logger.log_error(
+                        format!(
+                            "An unknown error occurred when trying to delete file {}",
+                            path.display()
+                        ),
+                        thread,
+                        verbose,
+                    ),
+                },
+            }
+        }
+
+        if let Some(dir) = move_processed {
+            match Self::move_to_dir(path, dir) {
+                Ok(moved_path) => logger.log_info(
+                    format!("Moved {} to {}", path.display(), moved_path.display()),
+                    thread,
+                    verbose,
+                ),
+                Err(err) => logger.log_error(
+                    format!(
+                        "Could not move {} to {}: {err}",
+                        path.display(),
+                        dir.display()
+                    ),
+                    thread,
+                    verbose,
+                ),
+            }
+        }
+    }
+
+    /// Moves `path` into `dir`, falling back to copying if both are on different file systems
+    fn move_to_dir(path: &Path, dir: &Path) -> io::Result<PathBuf> {
+        create_dir_all(dir)?;
+        let target = dir.join(path.file_name().unwrap_or_default());
+
+        if rename(path, &target).is_err() {
+            copy(path, &target)?;
+            remove_file(path)?;
+        }
+
+        Ok(target)
+    }
+
+    /// Encodes `path` into `outputs`, in segments or with two passes if the job asks for it.
+    /// With a target size and the video bitrate estimated for it, the bitrate is lowered and the
+    /// file encoded again while the outputs are too large. Returns the result of the last
+    /// attempt and the arguments that were added to the options for it.
+    #[allow(clippy::too_many_arguments)]
+    fn encode(
+        logger: &Logger,
+        path: &Path,
+        renditions: &[Rendition],
+        outputs: &[PathBuf],
+        loudnorm_args: Vec<String>,
+        two_pass: bool,
+        target_size: Option<(u64, u64)>,
+        segments: Option<(u64, usize)>,
+        overwrite: bool,
+        thread: u16,
+        verbose: bool,
+    ) -> Result<(io::Result<Output>, Vec<String>), String> {
+        // named after the process and thread, so parallel workers and other
+        // ffzap instances don't overwrite each other's first pass
+        let passlog = (two_pass || target_size.is_some())
+            .then(|| std::env::temp_dir().join(format!("ffzap-{}-{thread}", std::process::id())));
+        let mut video_bitrate = target_size.map(|(_, bitrate)| bitrate);
+
+        let mut attempt = 1;
+        let mut extra_args;
+        let result = loop {
+            extra_args = loudnorm_args.clone();
+            if let Some(bitrate) = video_bitrate {
+                extra_args.extend(["-b:v".to_string(), bitrate.to_string()]);
+            }
+
+            if passlog.is_some() {
+                logger.log_info(
+                    format!("Running first pass for {}", path.display()),
+                    thread,
+                    verbose,
+                );
+            }
+
+            // outputs of earlier attempts are replaced
+            let result = match segments {
+                Some((length, threads)) => Self::run_segmented(
+                    path,
+                    &renditions[0],
+                    &outputs[0],
+                    &extra_args,
+                    overwrite,
+                    length,
+                    threads,
+                    logger,
+                    thread,
+                    verbose,
+                ),
+                None => Self::run_ffmpeg(
+                    path,
+                    renditions,
+                    outputs,
+                    &extra_args,
+                    overwrite || attempt > 1,
+                    passlog.as_deref(),
+                ),
+            };
+
+            let (Some((target_size, _)), Some(bitrate), Ok(output)) =
+                (target_size, video_bitrate, &result)
+            else {
+                break result;
+            };
+
+            let size = Self::largest_size(outputs);
+            if !output.status.success()
+                || size <= target_size
+                || attempt == MAX_TARGET_SIZE_ATTEMPTS
+            {
+                break result;
+            }
+
+            let reduced = target_size::reduced_bitrate(bitrate, target_size, size);
+            logger.log_info(
+                format!(
+                    "{} is {size} bytes instead of at most {target_size}, retrying with a video bitrate of {reduced} bit/s",
+                    Self::display_outputs(outputs)
+                ),
+                thread,
+                verbose,
+            );
+            video_bitrate = Some(reduced);
+            attempt += 1;
+        };
+
+        if let Some(passlog) = &passlog {
+            Self::remove_passlogs(passlog);
+        }
+
+        let succeeded = result.as_ref().is_ok_and(|output| output.status.success());
+        if let (Some((target_size, _)), true) = (target_size, succeeded) {
+            if Self::largest_size(outputs) > target_size {
+                return Err(format!(
+                    "{} is still larger than {target_size} bytes after {attempt} attempts",
+                    Self::display_outputs(outputs)
+                ));
+            }
+        }
+
+        Ok((result, extra_args))
+    }
+
+    /// Compares the main output against `path` and, if it's below a threshold, encodes it again
+    /// with the `fallback` options. Returns the scores for the report, nothing if they couldn't
+    /// be measured without a threshold being set, or why the output was rejected.
+    #[allow(clippy::too_many_arguments)]
+    fn check_quality(
+        logger: &Logger,
+        path: &Path,
+        renditions: &[Rendition],
+        outputs: &[PathBuf],
+        extra_args: &[String],
+        fallback: Option<&str>,
+        (min_ssim, min_psnr): (Option<f64>, Option<f64>),
+        thread: u16,
+        verbose: bool,
+    ) -> Result<Option<String>, String> {
+        let mut scores = quality::measure(&outputs[0], path);
+
+        if let (Ok(current), Some(fallback)) = (&scores, fallback) {
+            if let Err(reason) = current.check(min_ssim, min_psnr) {
+                logger.log_info(
+                    format!(
+                        "{} has {current}, {reason}. Encoding it again with the quality fallback",
+                        outputs[0].display()
+                    ),
+                    thread,
+                    verbose,
+                );
+                let mut fallback_renditions = renditions.to_vec();
+                fallback_renditions[0].ffmpeg_options = Some(fallback.to_string());
+
+                scores = match Self::run_ffmpeg(
+                    path,
+                    &fallback_renditions,
+                    outputs,
+                    extra_args,
+                    true,
+                    None,
+                ) {
+                    Ok(output) if output.status.success() => quality::measure(&outputs[0], path),
+                    Ok(output) => Err(format!(
+                        "The fallback encode failed: {}",
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    )),
+                    Err(err) => Err(format!("The fallback encode failed: {err}")),
+                };
+            }
+        }
+
+        let checked = scores.and_then(|scores| {
+            logger.log_info(
+                format!("Quality of {}: {scores}", outputs[0].display()),
+                thread,
+                verbose,
+            );
+            scores
+                .check(min_ssim, min_psnr)
+                .map(|_| scores.to_string())
+                .map_err(|reason| format!("{reason} ({scores})"))
+        });
+
+        let has_threshold = min_ssim.is_some() || min_psnr.is_some();
+        match checked {
+            Ok(detail) => Ok(Some(detail)),
+            // nothing can be below a threshold that wasn't set
+            Err(reason) if !has_threshold => {
+                logger.log_warning(
+                    format!(
+                        "Could not measure the quality of {}: {reason}",
+                        outputs[0].display()
+                    ),
+                    thread,
+                    verbose,
+                );
+                Ok(None)
+            }
+            Err(reason) => Err(reason),
+        }
+    }
+
+    /// Removes `output` if it isn't at least `margin` percent smaller than `path`. Returns both
+    /// sizes for the report if it was removed.
+    fn discard_if_not_smaller(
+        logger: &Logger,
+        path: &Path,
+        output: &Path,
+        margin: f64,
+        thread: u16,
+        verbose: bool,
+    ) -> Option<String> {
+        let source_size = path.metadata().map_or(0, |metadata| metadata.len());
+        let output_size = output.metadata().map_or(0, |metadata| metadata.len());
+        if Self::is_beneficial(source_size, output_size, margin) {
+            return None;
+        }
+
+        logger.log_info(
+            format!("{} is {output_size} bytes and not at least {margin}% smaller than {source_size} bytes of {}, discarding it", output.display(), path.display()),
+            thread,
+            verbose,
+        );
+        if let Err(err) = remove_file(output) {
+            logger.log_error(
+                format!("Could not remove {}: {err}", output.display()),
+                thread,
+                verbose,
+            );
+        }
+
+        Some(format!(
+            "output {output_size} bytes, source {source_size} bytes"
+        ))
+    }
+
+    /// Copies `path` to where its main output was discarded, with the extension of the source.
+    /// Returns where the source can be found there, if it could be put in place.
+    fn copy_original(
+        logger: &Logger,
+        path: &Path,
+        outputs: &[PathBuf],
+        is_replaceable: impl Fn(&Path) -> bool,
+        written_outputs: &Mutex<HashSet<PathBuf>>,
+        thread: u16,
+        verbose: bool,
+    ) -> Option<PathBuf> {
+        let copy_path = outputs[0].with_extension(path.extension().unwrap_or_default());
+
+        // the output can be named like the source, which copying onto itself would truncate
+        let is_source = match (canonicalize(path), canonicalize(&copy_path)) {
+            (Ok(source), Ok(copy_path)) => source == copy_path,
+            _ => false,
+        };
+        if is_source {
+            logger.log_info(
+                format!("{} is already in place", path.display()),
+                thread,
+                verbose,
+            );
+            return Some(copy_path);
+        }
+
+        if outputs[1..].contains(&copy_path) {
+            logger.log_error(
+                format!(
+                    "Not copying {} to {}, it's one of the other outputs",
+                    path.display(),
+                    copy_path.display()
+                ),
+                thread,
+                verbose,
+            );
+            return None;
+        }
+        if copy_path.exists() && !is_replaceable(&copy_path) {
+            logger.log_error(
+                format!(
+                    "Not copying {} to {}, it already exists and --overwrite is set to false",
+                    path.display(),
+                    copy_path.display()
+                ),
+                thread,
+                verbose,
+            );
+            return None;
+        }
+
+        match copy(path, &copy_path) {
+            Ok(_) => {
+                written_outputs
+                    .lock()
+                    .unwrap()
+                    .insert(crate::resolve_output(&copy_path));
+                logger.log_info(
+                    format!("Copied {} to {}", path.display(), copy_path.display()),
+                    thread,
+                    verbose,
+                );
+                Some(copy_path)
+            }
+            Err(err) => {
+                logger.log_error(
+                    format!(
+                        "Could not copy {} to {}: {err}",
+                        path.display(),
+                        copy_path.display()
+                    ),
+                    thread,
+                    verbose,
+                );
+                None
+            }
+        }
+    }
+
+    /// The outputs of a file, for log messages
+    fn display_outputs(outputs: &[PathBuf]) -> String {
+        outputs
+            .iter()
+            .map(|output| output.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Runs ffmpeg for all renditions of `path`, appending `extra_args` to the options of each.
+    /// With a `passlog`, a first pass analyzes the file before the outputs are written.
+    fn run_ffmpeg(
+        path: &Path,
+        renditions: &[Rendition],
+        outputs: &[PathBuf],
+        extra_args: &[String],
+        overwrite: bool,
+        passlog: Option<&Path>,
+    ) -> io::Result<Output> {
+        // all renditions are created by a single ffmpeg run, so the input is
+        // only decoded once and the file only succeeds if every output does
+        let mut command = Command::new("ffmpeg");
+        command.arg("-i").arg(path);
+        for (i, (rendition, final_file_name)) in renditions.iter().zip(outputs).enumerate() {
+            command.args(rendition.split_options());
+            command.args(extra_args);
+            if let Some(passlog) = passlog {
+                command
+                    .args(["-pass", "2", "-passlogfile"])
+                    .arg(Self::passlog_prefix(passlog, i));
+            }
+            command.arg(final_file_name);
+        }
+        command.stdout(Stdio::null());
+        command.stderr(Stdio::piped());
+        #[cfg(target_os = "windows")]
+        command.creation_flags(0x08000000); // don't show cmd windows on Windows
+
+        if overwrite {
+            command.arg("-y");
+        }
+
+        if let Some(passlog) = passlog {
+            let first_pass =
+                Self::first_pass_command(path, renditions, extra_args, passlog).output()?;
+            if !first_pass.status.success() {
+                return Ok(first_pass);
+            }
+        }
+
+        command.output()
+    }
+
+    /// Splits `path` into chunks of about `length` seconds, encodes them on `threads` threads and
+    /// joins them into `output`. Returns the output of the step that failed, if any did.
+    #[allow(clippy::too_many_arguments)]
+    fn run_segmented(
+        path: &Path,
+        rendition: &Rendition,
+        output: &Path,
+        extra_args: &[String],
+        overwrite: bool,
+        length: u64,
+        threads: usize,
+        logger: &Logger,
+        thread: u16,
+        verbose: bool,
+    ) -> io::Result<Output> {
+        let dir =
+            std::env::temp_dir().join(format!("ffzap-{}-{thread}-segments", std::process::id()));
+        create_dir_all(&dir)?;
+
+        let options = rendition.split_options();
+        let result = segment::split(path, &dir, length).and_then(|(split, chunks)| {
+            if !split.status.success() {
+                return Ok(split);
+            }
+
+            logger.log_info(
+                format!(
+                    "Split {} into {} chunks, encoding them on {threads} threads",
+                    path.display(),
+                    chunks.len()
+                ),
+                thread,
+                verbose,
+            );
+            match segment::encode(&chunks, &options, threads)? {
+                Ok(encoded) => {
+                    segment::join(path, &encoded, output, &options, extra_args, overwrite)
+                }
+                Err(failed) => Ok(failed),
+            }
+        });
+
+        let _ = remove_dir_all(&dir);
+        result
+    }
+
+    /// Analyzes `path` for every rendition, without writing any output
+    fn first_pass_command(
+        path: &Path,
+        renditions: &[Rendition],
+        extra_args: &[String],
+        passlog: &Path,
+    ) -> Command {
+        let mut command = Command::new("ffmpeg");
+        command.arg("-y").arg("-i").arg(path);
+        for (i, rendition) in renditions.iter().enumerate() {
+            command.args(rendition.split_options());
+            command.args(extra_args);
+            command
+                .args(["-pass", "1", "-passlogfile"])
+                .arg(Self::passlog_prefix(passlog, i));
+            command.args(["-an", "-f", "null", "-"]);
+        }
+        command.stdout(Stdio::null());
+        command.stderr(Stdio::piped());
+        #[cfg(target_os = "windows")]
+        command.creation_flags(0x08000000); // don't show cmd windows on Windows
+
+        command
+    }
+
+    fn passlog_prefix(passlog: &Path, rendition: usize) -> PathBuf {
+        let mut prefix = passlog.as_os_str().to_os_string();
+        prefix.push(format!("-{rendition}"));
+        PathBuf::from(prefix)
+    }
+
+    /// Removes the files ffmpeg created for `passlog`, e.g. `<passlog>-0-0.log.mbtree` for x264
+    fn remove_passlogs(passlog: &Path) {
+        let (Some(dir), Some(prefix)) = (passlog.parent(), passlog.file_name()) else {
+            return;
+        };
+        let Ok(entries) = read_dir(dir) else {
+            return;
+        };
+
+        // the separator keeps thread 1 from matching the files of thread 10
+        let prefix = format!("{}-", prefix.to_string_lossy());
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                let _ = remove_file(entry.path());
+            }
+        }
+    }
+
+    /// Size of the largest of `outputs` in bytes
+    fn largest_size(outputs: &[PathBuf]) -> u64 {
+        outputs
+            .iter()
+            .filter_map(|output| output.metadata().ok())
+            .map(|metadata| metadata.len())
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Whether an output of `output_size` bytes is at least `margin` percent smaller than its
+    /// source
+    fn is_beneficial(source_size: u64, output_size: u64, margin: f64) -> bool {
+        (output_size as f64) < source_size as f64 * (1.0 - margin / 100.0)
+    }
+
+    /// Whether `output` was modified after `input`
+    fn is_newer(output: &Path, input: &Path) -> bool {
+        let modified = |path: &Path| path.metadata().and_then(|m| m.modified()).ok();
+
+        match (modified(output), modified(input)) {
+            (Some(output), Some(input)) => output >= input,
+            _ => false,
+        }
     }
 }
@@ -32,6 +32,10 @@ impl Progress {
         self.progress.inc(amount);
     }
 
+    pub fn inc_len(&self, amount: u64) {
+        self.progress.inc_length(amount);
+    }
+
     pub fn start_stick(&self, millis: u32) {
         self.progress.enable_steady_tick(Duration::new(0, millis));
     }
@@ -48,6 +52,10 @@ impl Progress {
         self.progress.length().unwrap()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn value(&self) -> u64 {
         self.progress.position()
     }
@@ -0,0 +1,120 @@
+use std::fmt;
+#[cfg(target_os = "windows")]
+use std::os::windows::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// How close an output is to its source. Identical videos have a PSNR of infinity.
+#[derive(Debug, Clone, Copy)]
+pub struct Scores {
+    pub ssim: Option<f64>,
+    pub psnr: Option<f64>,
+}
+
+pub fn parse_ssim(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(ssim) if (0.0..=1.0).contains(&ssim) => Ok(ssim),
+        _ => Err(format!(
+            "Invalid SSIM '{value}', expected a value from 0 to 1"
+        )),
+    }
+}
+
+pub fn parse_psnr(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(psnr) if psnr > 0.0 && psnr.is_finite() => Ok(psnr),
+        _ => Err(format!(
+            "Invalid PSNR '{value}', expected a positive number of dB"
+        )),
+    }
+}
+
+/// Compares the video of `output` against `source` with ffmpeg's ssim and psnr filters. The
+/// output is scaled to the size of the source first, so downscaled outputs can be compared.
+pub fn measure(output: &Path, source: &Path) -> Result<Scores, String> {
+    let mut command = Command::new("ffmpeg");
+    command
+        .arg("-hide_banner")
+        .arg("-i")
+        .arg(output)
+        .arg("-i")
+        .arg(source)
+        .arg("-lavfi")
+        .arg("[0:v][1:v]scale2ref[dist][ref];[dist]split[dist1][dist2];[ref]split[ref1][ref2];[dist1][ref1]ssim;[dist2][ref2]psnr")
+        .args(["-f", "null", "-"]);
+    command.stdout(Stdio::null());
+    command.stderr(Stdio::piped());
+    #[cfg(target_os = "windows")]
+    command.creation_flags(0x08000000); // don't show cmd windows on Windows
+
+    let output = command
+        .output()
+        .map_err(|err| format!("Could not run ffmpeg: {err}"))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    if !output.status.success() {
+        return Err(format!("The comparison failed: {}", stderr.trim()));
+    }
+
+    // e.g. "SSIM Y:0.995 (23.1) U:0.997 (25.3) V:0.996 (24.5) All:0.995 (23.7)"
+    // and "PSNR y:45.1 u:48.2 v:47.9 average:46.0 min:41.2 max:51.3"
+    let ssim = find_value(&stderr, "SSIM ", "All:");
+    let psnr = find_value(&stderr, "PSNR ", "average:");
+
+    if ssim.is_none() && psnr.is_none() {
+        return Err("ffmpeg didn't print any scores".to_string());
+    }
+
+    Ok(Scores { ssim, psnr })
+}
+
+/// Finds the number after `key` on the last line containing `marker`
+fn find_value(stderr: &str, marker: &str, key: &str) -> Option<f64> {
+    let line = stderr.lines().rev().find(|line| line.contains(marker))?;
+    let value = &line[line.find(key)? + key.len()..];
+    let value = value.split_whitespace().next()?;
+
+    match value {
+        "inf" => Some(f64::INFINITY),
+        value => value.parse().ok(),
+    }
+}
+
+impl Scores {
+    /// Returns why the scores are below the thresholds, if they are
+    pub fn check(&self, min_ssim: Option<f64>, min_psnr: Option<f64>) -> Result<(), String> {
+        let below = |name: &str, score: Option<f64>, min: Option<f64>| match (score, min) {
+            (Some(score), Some(min)) if score < min => {
+                Some(format!("{name} {score:.4} is below {min}"))
+            }
+            (None, Some(_)) => Some(format!("{name} could not be measured")),
+            _ => None,
+        };
+
+        let reasons: Vec<String> = [
+            below("SSIM", self.ssim, min_ssim),
+            below("PSNR", self.psnr, min_psnr),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        match reasons.is_empty() {
+            true => Ok(()),
+            false => Err(reasons.join(", ")),
+        }
+    }
+}
+
+impl fmt::Display for Scores {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ssim = self
+            .ssim
+            .map_or("unknown".to_string(), |ssim| format!("{ssim:.4}"));
+        let psnr = self
+            .psnr
+            .map_or("unknown".to_string(), |psnr| format!("{psnr:.2} dB"));
+
+        write!(f, "SSIM {ssim}, PSNR {psnr}")
+    }
+}
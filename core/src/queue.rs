@@ -0,0 +1,92 @@
+use crate::InputFile;
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+
+/// The paths waiting to be processed. While the queue is open, workers wait for new paths
+/// instead of shutting down once it runs empty.
+pub struct Queue {
+    state: Mutex<QueueState>,
+    available: Condvar,
+}
+
+struct QueueState {
+    paths: VecDeque<InputFile>,
+    closed: bool,
+    pushed: usize,
+}
+
+impl Queue {
+    /// Creates an open queue that workers keep waiting on until `close` is called
+    pub fn new() -> Self {
+        Queue {
+            state: Mutex::new(QueueState {
+                paths: VecDeque::new(),
+                closed: false,
+                pushed: 0,
+            }),
+            available: Condvar::new(),
+        }
+    }
+
+    /// Creates a closed queue that is done as soon as all `paths` are processed. They are
+    /// processed from last to first, but numbered in the order they were given.
+    pub fn from_paths(paths: Vec<InputFile>) -> Self {
+        let pushed = paths.len();
+        let paths = paths
+            .into_iter()
+            .enumerate()
+            .map(|(i, file)| InputFile {
+                index: i + 1,
+                ..file
+            })
+            .rev()
+            .collect();
+
+        Queue {
+            state: Mutex::new(QueueState {
+                paths,
+                closed: true,
+                pushed,
+            }),
+            available: Condvar::new(),
+        }
+    }
+
+    /// Adds `file` to the end of the queue and numbers it
+    pub fn push(&self, file: InputFile) {
+        let mut state = self.state.lock().unwrap();
+        state.pushed += 1;
+        let index = state.pushed;
+        state.paths.push_back(InputFile { index, ..file });
+        drop(state);
+
+        self.available.notify_one();
+    }
+
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.available.notify_all();
+    }
+
+    /// Blocks until a path is available. Returns `None` once the queue is closed and empty.
+    /// Paths come out in the order they were pushed, so `ffzap watch` processes files in the
+    /// order they arrived.
+    pub fn pop(&self) -> Option<InputFile> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(path) = state.paths.pop_front() {
+                return Some(path);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.available.wait(state).unwrap();
+        }
+    }
+}
+
+impl Default for Queue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,92 @@
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// What happened to a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Transcoded,
+    /// The streams were copied into the output without re-encoding them
+    Remuxed,
+    /// Matched `--skip-if`, or had no streams for `ffzap extract`
+    Skipped,
+    UpToDate,
+    /// The output wasn't smaller than the source and was discarded, see `--keep-smaller`
+    NotBeneficial,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportEntry {
+    pub input: String,
+    pub outputs: Vec<String>,
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// Collects the outcome of every file of a job, for `--report`
+#[derive(Default)]
+pub struct Report {
+    entries: Mutex<Vec<ReportEntry>>,
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Report::default()
+    }
+
+    pub fn record(
+        &self,
+        input: &Path,
+        outputs: &[impl AsRef<Path>],
+        outcome: Outcome,
+        detail: Option<String>,
+    ) {
+        self.entries.lock().unwrap().push(ReportEntry {
+            input: input.display().to_string(),
+            outputs: outputs
+                .iter()
+                .map(|output| output.as_ref().display().to_string())
+                .collect(),
+            outcome,
+            detail,
+        });
+    }
+
+    pub fn entries(&self) -> Vec<ReportEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    /// Whether `input` already has an entry
+    pub fn contains(&self, input: &Path) -> bool {
+        let input = input.display().to_string();
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|entry| entry.input == input)
+    }
+
+    pub fn count(&self, outcome: Outcome) -> usize {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|entry| entry.outcome == outcome)
+            .count()
+    }
+
+    /// Writes all entries as a JSON array
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let entries = self.entries.lock().unwrap();
+        fs::write(path, serde_json::to_vec_pretty(&*entries)?)
+    }
+}
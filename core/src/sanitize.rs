@@ -0,0 +1,183 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::ffi::{OsStr, OsString};
+use std::path::{Component, Path, PathBuf};
+
+/// The file system output paths have to be valid on
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sanitize {
+    /// No <>:"\|?* or control characters, no trailing dots or spaces, no reserved names like CON
+    Ntfs,
+    /// Same as ntfs, which is what exFAT and FAT32 long names allow as well
+    Fat,
+    /// Only limits names to 255 bytes
+    Posix,
+}
+
+const MAX_NAME_BYTES: usize = 255;
+
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+impl Sanitize {
+    /// Makes every file and directory name in `path` valid for the target file system.
+    /// Roots, prefixes and `.`/`..` are left untouched.
+    pub fn sanitize_path(self, path: &Path) -> PathBuf {
+        path.components()
+            .map(|component| match component {
+                Component::Normal(name) => self.sanitize_name(name),
+                other => other.as_os_str().to_os_string(),
+            })
+            .collect()
+    }
+
+    fn sanitize_name(self, name: &OsStr) -> OsString {
+        if self == Sanitize::Posix {
+            if name.len() <= MAX_NAME_BYTES {
+                return name.to_os_string();
+            }
+            return truncate_os_name(name);
+        }
+
+        let name: String = name
+            .to_string_lossy()
+            .chars()
+            .map(|c| match c {
+                '<' | '>' | ':' | '"' | '\\' | '|' | '?' | '*' => '_',
+                c if c.is_control() => '_',
+                c => c,
+            })
+            .collect();
+        let mut name = truncate_name(&name);
+
+        let trimmed_len = name.trim_end_matches(['.', ' ']).len();
+        name.truncate(trimmed_len);
+        if name.is_empty() {
+            name.push('_');
+        }
+
+        let stem = name.split('.').next().unwrap_or_default().trim_end();
+        if RESERVED_NAMES
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+        {
+            name.insert(0, '_');
+        }
+
+        OsString::from(name)
+    }
+}
+
+/// Shortens `name` to at most 255 bytes, keeping its extension
+fn truncate_name(name: &str) -> String {
+    // cuts only happen at character boundaries, so the result is valid UTF-8 again
+    String::from_utf8_lossy(&truncate_bytes(name.as_bytes())).to_string()
+}
+
+/// Shortens a name that may not be valid UTF-8 without replacing any of its bytes
+#[cfg(unix)]
+fn truncate_os_name(name: &OsStr) -> OsString {
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+    OsString::from_vec(truncate_bytes(name.as_bytes()))
+}
+
+#[cfg(not(unix))]
+fn truncate_os_name(name: &OsStr) -> OsString {
+    OsString::from(truncate_name(&name.to_string_lossy()))
+}
+
+fn truncate_bytes(name: &[u8]) -> Vec<u8> {
+    if name.len() <= MAX_NAME_BYTES {
+        return name.to_vec();
+    }
+
+    let (stem, ext) = match name.iter().rposition(|byte| *byte == b'.') {
+        Some(dot) if dot > 0 && name.len() - dot <= MAX_NAME_BYTES / 2 => name.split_at(dot),
+        _ => (name, &[][..]),
+    };
+
+    // never end in the middle of a UTF-8 sequence
+    let mut end = MAX_NAME_BYTES - ext.len();
+    while end > 0 && (stem[end] & 0b1100_0000) == 0b1000_0000 {
+        end -= 1;
+    }
+
+    [&stem[..end], ext].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ntfs(path: &str) -> PathBuf {
+        Sanitize::Ntfs.sanitize_path(Path::new(path))
+    }
+
+    #[test]
+    fn replaces_invalid_characters() {
+        assert_eq!(ntfs("out/a?b*c:d.mkv"), PathBuf::from("out/a_b_c_d.mkv"));
+        assert_eq!(ntfs("out/tab\there.mkv"), PathBuf::from("out/tab_here.mkv"));
+    }
+
+    #[test]
+    fn prefixes_reserved_names() {
+        assert_eq!(ntfs("out/con.mkv"), PathBuf::from("out/_con.mkv"));
+        assert_eq!(ntfs("out/LPT1"), PathBuf::from("out/_LPT1"));
+        assert_eq!(ntfs("nul.tar.gz"), PathBuf::from("_nul.tar.gz"));
+        assert_eq!(ntfs("out/console.mkv"), PathBuf::from("out/console.mkv"));
+    }
+
+    #[test]
+    fn trims_trailing_dots_and_spaces() {
+        assert_eq!(ntfs("out/name. . /a.mkv"), PathBuf::from("out/name/a.mkv"));
+        assert_eq!(ntfs("out/.../a.mkv"), PathBuf::from("out/_/a.mkv"));
+    }
+
+    #[test]
+    fn keeps_roots_and_parent_dirs() {
+        assert_eq!(ntfs("/out/../a?.mkv"), PathBuf::from("/out/../a_.mkv"));
+        assert_eq!(ntfs("./a.mkv"), PathBuf::from("./a.mkv"));
+    }
+
+    #[test]
+    fn truncates_at_character_boundaries() {
+        // 'ä' takes two bytes, so 255 bytes would end in the middle of one
+        let name = format!("{}.mkv", "ä".repeat(200));
+        let truncated = ntfs(&name);
+        let truncated = truncated.to_str().unwrap();
+
+        assert_eq!(truncated.len(), 254);
+        assert!(truncated.ends_with("ä.mkv"));
+        assert_eq!(
+            Sanitize::Posix.sanitize_path(Path::new(&name)),
+            PathBuf::from(truncated)
+        );
+    }
+
+    #[test]
+    fn keeps_short_names() {
+        let name = format!("out/{}.mkv", "a".repeat(251));
+        assert_eq!(
+            Sanitize::Posix.sanitize_path(Path::new(&name)),
+            PathBuf::from(&name)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn posix_keeps_invalid_utf8() {
+        use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+        let mut name = vec![0xff; 300];
+        name.extend(b".mkv");
+        let truncated = Sanitize::Posix.sanitize_path(Path::new(OsStr::from_bytes(&name)));
+
+        let mut expected = vec![0xff; 251];
+        expected.extend(b".mkv");
+        assert_eq!(truncated, PathBuf::from(OsString::from_vec(expected)));
+    }
+}
@@ -0,0 +1,182 @@
+use std::fs::{read_dir, write};
+use std::io;
+#[cfg(target_os = "windows")]
+use std::os::windows::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Options that can't be split across chunks, as they refer to streams of the whole input
+const UNSUPPORTED_OPTIONS: [&str; 3] = ["-map", "-filter_complex", "-lavfi"];
+
+/// Options that only apply to the video, which is already encoded when the chunks are joined
+const VIDEO_FILTER_OPTIONS: [&str; 3] = ["-vf", "-filter:v", "-filter:v:0"];
+
+/// Checks that `ffmpeg_options` can be used for segmented encoding
+pub fn check_options(ffmpeg_options: &[&str]) -> Result<(), String> {
+    match ffmpeg_options
+        .iter()
+        .find(|option| UNSUPPORTED_OPTIONS.contains(option))
+    {
+        Some(option) => Err(format!("{option} can't be used with --segment-length")),
+        None => Ok(()),
+    }
+}
+
+/// Copies the first video stream of `input` into chunks of about `length` seconds in `dir`.
+/// Chunks always start at a keyframe, so they can be longer.
+pub fn split(input: &Path, dir: &Path, length: u64) -> io::Result<(Output, Vec<PathBuf>)> {
+    let mut command = ffmpeg();
+    command
+        .arg("-i")
+        .arg(input)
+        .args(["-map", "0:v:0", "-c", "copy", "-f", "segment"])
+        .args([
+            "-segment_time",
+            &length.to_string(),
+            "-reset_timestamps",
+            "1",
+        ])
+        .arg(dir.join("chunk-%05d.mkv"));
+
+    let output = command.output()?;
+
+    let mut chunks = read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("chunk-"))
+        })
+        .collect::<Vec<_>>();
+    chunks.sort();
+
+    Ok((output, chunks))
+}
+
+/// Encodes every chunk with `ffmpeg_options` on `threads` threads. Returns the encoded chunks
+/// in order, or the output of the first encode that failed.
+pub fn encode(
+    chunks: &[PathBuf],
+    ffmpeg_options: &[&str],
+    threads: usize,
+) -> io::Result<Result<Vec<PathBuf>, Output>> {
+    let encoded = chunks
+        .iter()
+        .map(|chunk| {
+            let name = chunk.file_name().unwrap_or_default().to_string_lossy();
+            chunk.with_file_name(name.replacen("chunk-", "encoded-", 1))
+        })
+        .collect::<Vec<_>>();
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let failure: Mutex<Option<io::Result<Output>>> = Mutex::new(None);
+
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| {
+                while !failed.load(Ordering::Relaxed) {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(chunk) = chunks.get(i) else {
+                        break;
+                    };
+
+                    let mut command = ffmpeg();
+                    command
+                        .arg("-i")
+                        .arg(chunk)
+                        .args(ffmpeg_options)
+                        .args(["-an", "-sn", "-dn"])
+                        .arg(&encoded[i]);
+
+                    match command.output() {
+                        Ok(output) if output.status.success() => {}
+                        result => {
+                            failed.store(true, Ordering::Relaxed);
+                            failure.lock().unwrap().get_or_insert(result);
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    match failure.into_inner().unwrap() {
+        Some(result) => result.map(Err),
+        None => Ok(Ok(encoded)),
+    }
+}
+
+/// Joins the encoded chunks with the concat demuxer and adds all other streams of `input`,
+/// which are encoded with `ffmpeg_options` in one piece.
+pub fn join(
+    input: &Path,
+    encoded: &[PathBuf],
+    output: &Path,
+    ffmpeg_options: &[&str],
+    extra_args: &[String],
+    overwrite: bool,
+) -> io::Result<Output> {
+    let dir = encoded
+        .first()
+        .and_then(|chunk| chunk.parent())
+        .ok_or_else(|| io::Error::other("There are no chunks to join"))?;
+    let list = dir.join("chunks.txt");
+    let lines = encoded
+        .iter()
+        .map(|chunk| {
+            format!(
+                "file '{}'\n",
+                chunk.to_string_lossy().replace('\'', r"'\''")
+            )
+        })
+        .collect::<String>();
+    write(&list, lines)?;
+
+    let mut command = ffmpeg();
+    command
+        .args(["-f", "concat", "-safe", "0", "-i"])
+        .arg(&list)
+        .arg("-i")
+        .arg(input)
+        .args(["-map", "0:v", "-map", "1", "-map", "-1:v"])
+        .args(without_video_filters(ffmpeg_options))
+        .args(extra_args)
+        .args(["-c:v", "copy"])
+        .arg(output);
+    if overwrite {
+        command.arg("-y");
+    }
+
+    command.output()
+}
+
+fn without_video_filters<'a>(ffmpeg_options: &[&'a str]) -> Vec<&'a str> {
+    let mut options = vec![];
+    let mut skip_value = false;
+
+    for option in ffmpeg_options {
+        if skip_value {
+            skip_value = false;
+        } else if VIDEO_FILTER_OPTIONS.contains(option) {
+            skip_value = true;
+        } else {
+            options.push(*option);
+        }
+    }
+
+    options
+}
+
+fn ffmpeg() -> Command {
+    let mut command = Command::new("ffmpeg");
+    command.arg("-hide_banner");
+    command.stdout(Stdio::null());
+    command.stderr(Stdio::piped());
+    #[cfg(target_os = "windows")]
+    command.creation_flags(0x08000000); // don't show cmd windows on Windows
+
+    command
+}
@@ -0,0 +1,77 @@
+use crate::filter::parse_number;
+use crate::MediaInfo;
+
+/// Share of the target size that is left for the streams, the rest is muxing overhead
+const PAYLOAD_SHARE: f64 = 0.97;
+
+/// Below this, the video would be unwatchable and the target size is probably a mistake
+const MIN_VIDEO_BITRATE: u64 = 50_000;
+
+/// Used if neither the ffmpeg options nor the input tell the audio bitrate
+const DEFAULT_AUDIO_BITRATE: u64 = 128_000;
+
+/// Parses a size in bytes with an optional k, M or G suffix for use as a clap value parser,
+/// e.g. `25M`.
+pub fn parse(value: &str) -> Result<u64, String> {
+    match parse_number(value) {
+        Some(size) if size >= 1.0 => Ok(size as u64),
+        _ => Err(format!(
+            "Invalid size '{value}', expected a number of bytes like 25M or 500k"
+        )),
+    }
+}
+
+/// The video bitrate in bits per second that makes a file of `info`'s duration fit into
+/// `target_size` bytes, after leaving room for the audio.
+pub fn video_bitrate(
+    info: &MediaInfo,
+    target_size: u64,
+    ffmpeg_options: Option<&str>,
+) -> Result<u64, String> {
+    let duration = info
+        .duration()
+        .filter(|duration| *duration > 0.0)
+        .ok_or("The duration is unknown")?;
+
+    let total_bitrate = target_size as f64 * 8.0 * PAYLOAD_SHARE / duration;
+    let video_bitrate = total_bitrate - audio_bitrate(info, ffmpeg_options) as f64;
+
+    if video_bitrate < MIN_VIDEO_BITRATE as f64 {
+        return Err(format!(
+            "A file of {duration:.0} seconds doesn't fit into {target_size} bytes"
+        ));
+    }
+
+    Ok(video_bitrate as u64)
+}
+
+/// A lower bitrate for another attempt, after an encode with `bitrate` ended up at
+/// `actual_size` instead of `target_size` bytes
+pub fn reduced_bitrate(bitrate: u64, target_size: u64, actual_size: u64) -> u64 {
+    let reduced = bitrate as f64 * target_size as f64 / actual_size as f64 * 0.95;
+    (reduced as u64).max(MIN_VIDEO_BITRATE)
+}
+
+fn audio_bitrate(info: &MediaInfo, ffmpeg_options: Option<&str>) -> u64 {
+    let options = ffmpeg_options
+        .unwrap_or_default()
+        .split(' ')
+        .collect::<Vec<&str>>();
+
+    if options.contains(&"-an") || !info.has_stream("audio") {
+        return 0;
+    }
+
+    let from_options = options
+        .windows(2)
+        .find(|pair| pair[0] == "-b:a" || pair[0] == "-ab")
+        .and_then(|pair| parse_number(pair[1]));
+
+    match from_options {
+        Some(bitrate) => bitrate as u64,
+        None => info
+            .first_stream("audio")
+            .and_then(|stream| stream.bit_rate.as_ref()?.parse().ok())
+            .unwrap_or(DEFAULT_AUDIO_BITRATE),
+    }
+}
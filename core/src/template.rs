@@ -0,0 +1,646 @@
+use crate::extract;
+use crate::probe::{MediaInfo, StreamInfo};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+
+/// A parsed `--output` pattern like `out/{{mtime:%Y}}/{{name|slug}}_{{width}}p.{{ext}}`
+#[derive(Debug, Clone)]
+pub struct OutputPattern {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+#[derive(Debug, Clone)]
+struct Placeholder {
+    name: String,
+    arg: Option<String>,
+    transforms: Vec<Transform>,
+}
+
+/// A filter applied to the value of a placeholder, e.g. `|lower` in `{{name|lower}}`
+#[derive(Debug, Clone)]
+enum Transform {
+    Lower,
+    Upper,
+    Slug,
+    Replace(String, String),
+    Truncate(usize),
+    /// Replaces whole values, e.g. `avi=mkv` in `{{ext|map:avi=mkv,wmv=mp4}}`
+    Map(Vec<(String, String)>),
+    Default(DefaultValue),
+}
+
+/// The fallback of `|default`, either another placeholder or a quoted literal
+#[derive(Debug, Clone)]
+enum DefaultValue {
+    Placeholder(Box<Placeholder>),
+    Literal(String),
+}
+
+/// Everything a single output path can be built from
+pub struct TemplateContext<'a> {
+    pub path: &'a Path,
+    pub base_dir: Option<&'a Path>,
+    /// Only available if the pattern uses metadata placeholders
+    pub media_info: Option<&'a MediaInfo>,
+    /// 1-based position of the file in the job
+    pub index: usize,
+    pub total: usize,
+    pub started: DateTime<Local>,
+    /// 1-based number of the image, only set by `ffzap thumbs`
+    pub frame: Option<usize>,
+    /// How many images `ffzap thumbs` creates per file
+    pub frames: usize,
+    /// The stream written by `ffzap extract`
+    pub stream: Option<&'a StreamInfo>,
+}
+
+const PLACEHOLDERS: [&str; 19] = [
+    "ext",
+    "name",
+    "dir",
+    "reldir",
+    "parent",
+    "width",
+    "height",
+    "vcodec",
+    "acodec",
+    "duration",
+    "title",
+    "tag",
+    "mtime",
+    "date",
+    "index",
+    "frame",
+    "stream_index",
+    "lang",
+    "codec",
+];
+
+const METADATA_PLACEHOLDERS: [&str; 7] = [
+    "width", "height", "vcodec", "acodec", "duration", "title", "tag",
+];
+
+impl OutputPattern {
+    pub fn parse(pattern: &str) -> Result<OutputPattern, String> {
+        let mut segments = vec![];
+        let mut rest = pattern;
+
+        while let Some(start) = rest.find("{{") {
+            let Some(end) = rest[start..].find("}}").map(|end| start + end) else {
+                break;
+            };
+
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+
+            let inner = &rest[start + 2..end];
+            let mut parts = split_unquoted(inner, '|').into_iter();
+            let mut placeholder = Placeholder::parse(&parts.next().unwrap_or_default())?;
+            for part in parts {
+                placeholder.transforms.push(Transform::parse(&part)?);
+            }
+            segments.push(Segment::Placeholder(placeholder));
+
+            rest = &rest[end + 2..];
+        }
+
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+
+        Ok(OutputPattern { segments })
+    }
+
+    /// Maps the value of every `{{ext}}` before any of its filters are applied, for `--ext-map`
+    pub fn map_extensions(&mut self, mapping: &str) -> Result<(), String> {
+        let mapping = parse_map(mapping)?;
+
+        for segment in &mut self.segments {
+            if let Segment::Placeholder(placeholder) = segment {
+                if placeholder.name == "ext" {
+                    placeholder
+                        .transforms
+                        .insert(0, Transform::Map(mapping.clone()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether rendering this pattern requires probing the input with ffprobe
+    pub fn needs_metadata(&self) -> bool {
+        self.segments.iter().any(|segment| match segment {
+            Segment::Placeholder(placeholder) => placeholder.needs_metadata(),
+            Segment::Literal(_) => false,
+        })
+    }
+
+    pub fn render(&self, context: &TemplateContext) -> PathBuf {
+        let mut final_file_name = OsString::new();
+
+        // placeholders are filled in as OsStr, so paths that aren't valid UTF-8 survive
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Literal(literal) => {
+                    // files without an extension shouldn't end up as `name.`
+                    let next_is_empty_ext = match self.segments.get(i + 1) {
+                        Some(Segment::Placeholder(placeholder)) if placeholder.name == "ext" => {
+                            placeholder.render(context).is_empty()
+                        }
+                        _ => false,
+                    };
+
+                    match literal.strip_suffix('.') {
+                        Some(literal) if next_is_empty_ext => final_file_name.push(literal),
+                        _ => final_file_name.push(literal),
+                    }
+                }
+                Segment::Placeholder(placeholder) => {
+                    final_file_name.push(placeholder.render(context))
+                }
+            }
+        }
+
+        PathBuf::from(final_file_name)
+    }
+}
+
+/// Checks an output pattern for use as a clap value parser.
+pub fn validate(pattern: &str) -> Result<String, String> {
+    OutputPattern::parse(pattern).map(|_| pattern.to_string())
+}
+
+/// Checks a list like `avi=mkv,wmv=mp4` for use as a clap value parser.
+pub fn validate_map(mapping: &str) -> Result<String, String> {
+    parse_map(mapping).map(|_| mapping.to_string())
+}
+
+/// Parses a comma separated list of `from=to` pairs
+fn parse_map(mapping: &str) -> Result<Vec<(String, String)>, String> {
+    mapping
+        .split(',')
+        .map(|pair| match pair.split_once('=') {
+            Some((from, to)) if !from.trim().is_empty() => {
+                Ok((from.trim().to_string(), to.trim().to_string()))
+            }
+            _ => Err(format!(
+                "Invalid mapping '{pair}', expected a list like avi=mkv,wmv=mp4"
+            )),
+        })
+        .collect()
+}
+
+impl Placeholder {
+    /// Parses `name` or `name:arg`, without any transforms
+    fn parse(text: &str) -> Result<Placeholder, String> {
+        let (name, arg) = match text.split_once(':') {
+            Some((name, arg)) => (name.trim(), Some(arg.to_string())),
+            None => (text.trim(), None),
+        };
+
+        let placeholder = Placeholder {
+            name: name.to_string(),
+            arg,
+            transforms: vec![],
+        };
+        placeholder.validate()?;
+        Ok(placeholder)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match (self.name.as_str(), &self.arg) {
+            (name, _) if !PLACEHOLDERS.contains(&name) => Err(format!(
+                "Unknown placeholder {{{{{name}}}}}. Available placeholders are: {}",
+                PLACEHOLDERS.join(", ")
+            )),
+            ("mtime" | "date", Some(format))
+                if StrftimeItems::new(format).any(|item| item == Item::Error) =>
+            {
+                Err(format!(
+                    "Invalid date format '{format}' in {{{{{}}}}}",
+                    self.name
+                ))
+            }
+            ("index" | "frame", Some(width)) if width.parse::<usize>().is_err() => Err(format!(
+                "Invalid padding '{width}' in {{{{{}}}}}",
+                self.name
+            )),
+            ("tag", None) => {
+                Err("{{tag}} needs the name of a tag, e.g. {{tag:artist}}".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn needs_metadata(&self) -> bool {
+        METADATA_PLACEHOLDERS.contains(&self.name.as_str())
+            || self.transforms.iter().any(|transform| match transform {
+                Transform::Default(DefaultValue::Placeholder(placeholder)) => {
+                    placeholder.needs_metadata()
+                }
+                _ => false,
+            })
+    }
+
+    fn render(&self, context: &TemplateContext) -> OsString {
+        let value = self.render_value(context);
+        if self.transforms.is_empty() {
+            return value;
+        }
+
+        // transforms work on text, so only these placeholders lose non-UTF-8 bytes
+        let mut value = value.to_string_lossy().to_string();
+        for transform in &self.transforms {
+            value = transform.apply(value, context);
+        }
+
+        OsString::from(value)
+    }
+
+    fn render_value(&self, context: &TemplateContext) -> OsString {
+        let path = context.path;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let info = context.media_info;
+        let video = info.and_then(|info| info.first_stream("video"));
+        let audio = info.and_then(|info| info.first_stream("audio"));
+
+        let value: String = match self.name.as_str() {
+            "ext" => {
+                return match context.stream {
+                    Some(stream) => OsString::from(extract::container(stream).0),
+                    None => path.extension().unwrap_or_default().to_os_string(),
+                }
+            }
+            "name" => return path.file_stem().unwrap_or_default().to_os_string(),
+            "dir" => return dir.as_os_str().to_os_string(),
+            "reldir" => return relative_dir(dir, context.base_dir).into_os_string(),
+            "parent" => return dir.file_name().unwrap_or_default().to_os_string(),
+            "width" => video
+                .and_then(|s| s.width)
+                .map(|w| w.to_string())
+                .unwrap_or_default(),
+            "height" => video
+                .and_then(|s| s.height)
+                .map(|h| h.to_string())
+                .unwrap_or_default(),
+            "vcodec" => video.and_then(|s| s.codec_name.clone()).unwrap_or_default(),
+            "acodec" => audio.and_then(|s| s.codec_name.clone()).unwrap_or_default(),
+            "duration" => info
+                .and_then(|info| info.duration())
+                .map(|d| format!("{:.0}", d))
+                .unwrap_or_default(),
+            "title" => tag_value(info, "title"),
+            "tag" => tag_value(info, self.arg.as_deref().unwrap_or_default()),
+            "mtime" => {
+                let modified = path
+                    .metadata()
+                    .and_then(|m| m.modified())
+                    .map(DateTime::<Local>::from);
+                match modified {
+                    Ok(modified) => modified.format(self.date_format()).to_string(),
+                    Err(_) => String::new(),
+                }
+            }
+            "date" => context.started.format(self.date_format()).to_string(),
+            "index" => {
+                let width = match &self.arg {
+                    Some(width) => width.parse().unwrap_or_default(),
+                    None => context.total.to_string().len(),
+                };
+                format!("{:0width$}", context.index)
+            }
+            "stream_index" => context
+                .stream
+                .map(|stream| stream.index.to_string())
+                .unwrap_or_default(),
+            "lang" => context
+                .stream
+                .map(|stream| {
+                    stream
+                        .tags
+                        .get("language")
+                        .cloned()
+                        .unwrap_or("und".to_string())
+                })
+                .unwrap_or_default(),
+            "codec" => context
+                .stream
+                .and_then(|stream| stream.codec_name.clone())
+                .unwrap_or_default(),
+            "frame" => match context.frame {
+                Some(frame) => {
+                    let width = match &self.arg {
+                        Some(width) => width.parse().unwrap_or_default(),
+                        None => context.frames.to_string().len(),
+                    };
+                    format!("{frame:0width$}")
+                }
+                None => String::new(),
+            },
+            // unknown placeholders are rejected when parsing
+            _ => String::new(),
+        };
+
+        OsString::from(value)
+    }
+
+    fn date_format(&self) -> &str {
+        self.arg.as_deref().unwrap_or("%Y-%m-%d")
+    }
+}
+
+impl Transform {
+    /// Parses `name` or `name:arg:arg`. Arguments can be quoted to contain `:`, `|` or spaces.
+    fn parse(text: &str) -> Result<Transform, String> {
+        let text = text.trim();
+        let (name, args) = match text.split_once(':') {
+            Some((name, args)) => (name.trim(), split_unquoted(args, ':')),
+            None => (text, vec![]),
+        };
+
+        let wrong_args = |usage: &str| format!("Filter '{name}' expects {usage}");
+
+        match (name, args.as_slice()) {
+            ("lower", []) => Ok(Transform::Lower),
+            ("upper", []) => Ok(Transform::Upper),
+            ("slug", []) => Ok(Transform::Slug),
+            ("lower" | "upper" | "slug", _) => Err(wrong_args("no arguments")),
+            ("replace", [from, to]) => Ok(Transform::Replace(unquote(from).0, unquote(to).0)),
+            ("replace", _) => Err(wrong_args(r#"two arguments, e.g. replace:" ":"_""#)),
+            ("truncate", [length]) => unquote(length)
+                .0
+                .parse()
+                .map(Transform::Truncate)
+                .map_err(|_| wrong_args("a number of characters, e.g. truncate:40")),
+            ("truncate", _) => Err(wrong_args("a number of characters, e.g. truncate:40")),
+            ("map", [mapping]) => parse_map(&unquote(mapping).0).map(Transform::Map),
+            ("map", _) => Err(wrong_args("a list of replacements, e.g. map:avi=mkv,wmv=mp4")),
+            ("default", [fallback]) => match unquote(fallback) {
+                (literal, true) => Ok(Transform::Default(DefaultValue::Literal(literal))),
+                (name, false) => Ok(Transform::Default(DefaultValue::Placeholder(Box::new(
+                    Placeholder::parse(&name)?,
+                )))),
+            },
+            ("default", _) => Err(wrong_args(
+                r#"a placeholder or quoted text, e.g. default:name or default:"untitled""#,
+            )),
+            _ => Err(format!(
+                "Unknown filter '{name}'. Available filters are: lower, upper, slug, replace, truncate, map, default"
+            )),
+        }
+    }
+
+    fn apply(&self, value: String, context: &TemplateContext) -> String {
+        match self {
+            Transform::Lower => value.to_lowercase(),
+            Transform::Upper => value.to_uppercase(),
+            Transform::Slug => slugify(&value),
+            Transform::Replace(from, to) => value.replace(from.as_str(), to),
+            Transform::Truncate(length) => value.chars().take(*length).collect(),
+            Transform::Map(mapping) => mapping
+                .iter()
+                .find(|(from, _)| from.eq_ignore_ascii_case(&value))
+                .map(|(_, to)| to.clone())
+                .unwrap_or(value),
+            Transform::Default(_) if !value.is_empty() => value,
+            Transform::Default(DefaultValue::Literal(literal)) => literal.clone(),
+            Transform::Default(DefaultValue::Placeholder(placeholder)) => {
+                placeholder.render(context).to_string_lossy().to_string()
+            }
+        }
+    }
+}
+
+/// Lowercases `value` and joins runs of alphanumeric characters with `-`
+fn slugify(value: &str) -> String {
+    value
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Splits `text` at every `separator` that isn't inside double quotes. Quotes are kept.
+fn split_unquoted(text: &str, separator: char) -> Vec<String> {
+    let mut parts = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for c in text.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            c if c == separator && !quoted => parts.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    parts.push(current);
+
+    parts
+}
+
+/// Removes surrounding double quotes. Returns whether `text` was quoted.
+fn unquote(text: &str) -> (String, bool) {
+    let trimmed = text.trim();
+    match trimmed
+        .strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+    {
+        Some(unquoted) => (unquoted.to_string(), true),
+        None => (trimmed.to_string(), false),
+    }
+}
+
+/// Looks up a container tag, falling back to the tags of the streams. Path separators are
+/// replaced so tags can't create directories.
+fn tag_value(info: Option<&MediaInfo>, key: &str) -> String {
+    let Some(info) = info else {
+        return String::new();
+    };
+
+    let find = |tags: &std::collections::HashMap<String, String>| {
+        tags.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.clone())
+    };
+
+    find(&info.format.tags)
+        .or_else(|| info.streams.iter().find_map(|s| find(&s.tags)))
+        .unwrap_or_default()
+        .replace(['/', '\\'], "_")
+}
+
+/// `dir` relative to `base_dir`. Without a matching base directory, `dir` is made relative
+/// by dropping its root, so it can still be appended to another directory.
+fn relative_dir(dir: &Path, base_dir: Option<&Path>) -> PathBuf {
+    if let Some(relative) = base_dir.and_then(|base_dir| dir.strip_prefix(base_dir).ok()) {
+        return relative.to_path_buf();
+    }
+
+    dir.components()
+        .filter(|c| matches!(c, Component::Normal(_) | Component::ParentDir))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::FormatInfo;
+    use std::collections::HashMap;
+
+    fn render(pattern: &str, path: &str) -> PathBuf {
+        let info = MediaInfo {
+            streams: vec![StreamInfo {
+                index: 0,
+                codec_type: Some("video".to_string()),
+                codec_name: Some("hevc".to_string()),
+                width: Some(1920),
+                ..Default::default()
+            }],
+            format: FormatInfo {
+                tags: HashMap::from([("ARTIST".to_string(), "AC/DC".to_string())]),
+                ..Default::default()
+            },
+        };
+        let context = TemplateContext {
+            path: Path::new(path),
+            base_dir: Some(Path::new("in")),
+            media_info: Some(&info),
+            index: 7,
+            total: 120,
+            started: Local::now(),
+            frame: None,
+            frames: 0,
+            stream: None,
+        };
+
+        OutputPattern::parse(pattern).unwrap().render(&context)
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        assert_eq!(
+            render(
+                "out/{{reldir}}/{{index}}_{{name}}_{{width}}p.{{ext}}",
+                "in/a/b/My Clip.mkv"
+            ),
+            PathBuf::from("out/a/b/007_My Clip_1920p.mkv")
+        );
+        assert_eq!(
+            render("{{index:2}}-{{tag:artist}}", "x.mp4"),
+            PathBuf::from("07-AC_DC")
+        );
+    }
+
+    #[test]
+    fn drops_the_dot_before_an_empty_extension() {
+        assert_eq!(
+            render("out/{{name}}.{{ext}}", "in/README"),
+            PathBuf::from("out/README")
+        );
+        assert_eq!(
+            render("out/{{name}}.{{ext|default:\"bin\"}}", "in/README"),
+            PathBuf::from("out/README.bin")
+        );
+    }
+
+    #[test]
+    fn applies_filters_with_quoted_arguments() {
+        assert_eq!(
+            render("{{name|replace:\" \":\"_\"|lower}}", "My Clip.mkv"),
+            PathBuf::from("my_clip")
+        );
+        assert_eq!(
+            render("{{name|replace:\"|\":\"-\"}}", "a|b.mkv"),
+            PathBuf::from("a-b")
+        );
+        assert_eq!(
+            render("{{name|slug|truncate:6}}", "Hello, World!.mkv"),
+            PathBuf::from("hello-")
+        );
+        assert_eq!(
+            render("{{name}}.{{ext|map:avi=mkv,wmv=mp4}}", "a.AVI"),
+            PathBuf::from("a.mkv")
+        );
+        assert_eq!(
+            render("{{title|default:name}}", "clip.mkv"),
+            PathBuf::from("clip")
+        );
+    }
+
+    #[test]
+    fn maps_extensions_before_filters() {
+        let mut pattern = OutputPattern::parse("{{name}}.{{ext|upper}}").unwrap();
+        pattern.map_extensions("avi=mkv").unwrap();
+        let context = TemplateContext {
+            path: Path::new("a.avi"),
+            base_dir: None,
+            media_info: None,
+            index: 1,
+            total: 1,
+            started: Local::now(),
+            frame: None,
+            frames: 0,
+            stream: None,
+        };
+
+        assert_eq!(pattern.render(&context), PathBuf::from("a.MKV"));
+    }
+
+    #[test]
+    fn rejects_unknown_placeholders_and_filters() {
+        assert!(OutputPattern::parse("{{nmae}}.mkv")
+            .unwrap_err()
+            .starts_with("Unknown placeholder {{nmae}}"));
+        assert!(OutputPattern::parse("{{name|snake}}")
+            .unwrap_err()
+            .starts_with("Unknown filter 'snake'"));
+        assert_eq!(
+            OutputPattern::parse("{{name|replace:\"_\"}}").unwrap_err(),
+            "Filter 'replace' expects two arguments, e.g. replace:\" \":\"_\""
+        );
+        assert_eq!(
+            OutputPattern::parse("{{index:wide}}").unwrap_err(),
+            "Invalid padding 'wide' in {{index}}"
+        );
+        assert_eq!(
+            OutputPattern::parse("{{tag}}").unwrap_err(),
+            "{{tag}} needs the name of a tag, e.g. {{tag:artist}}"
+        );
+    }
+
+    #[test]
+    fn needs_metadata_only_for_probed_values() {
+        let needs_metadata =
+            |pattern: &str| OutputPattern::parse(pattern).unwrap().needs_metadata();
+
+        assert!(!needs_metadata("{{dir}}/{{name}}.{{ext}}"));
+        assert!(needs_metadata("{{name}}_{{height}}p.mkv"));
+        assert!(needs_metadata("{{name|default:title}}.mkv"));
+    }
+
+    #[test]
+    fn relative_dir_falls_back_to_dropping_the_root() {
+        assert_eq!(
+            relative_dir(Path::new("/media/in/a"), Some(Path::new("/media/in"))),
+            PathBuf::from("a")
+        );
+        assert_eq!(
+            relative_dir(Path::new("/media/other"), Some(Path::new("/media/in"))),
+            PathBuf::from("media/other")
+        );
+    }
+}
@@ -0,0 +1,58 @@
+use crate::args::ThumbsArgs;
+use crate::MediaInfo;
+
+/// The ffmpeg options of every image `ffzap thumbs` creates from a file of `info`'s duration.
+/// Frames are taken from the middle of equally long sections, so the first and last frames,
+/// which are often black, are avoided.
+pub fn image_options(args: &ThumbsArgs, info: &MediaInfo) -> Result<Vec<String>, String> {
+    let duration = info
+        .duration()
+        .filter(|duration| *duration > 0.0)
+        .ok_or("The duration is unknown")?;
+    let scale = args.width.map(|width| format!("scale={width}:-1"));
+
+    match &args.sheet {
+        Some(sheet) => {
+            let (columns, rows) = parse_grid(sheet)?;
+            let filters = [
+                Some(format!("fps={}/{duration}", columns * rows)),
+                scale,
+                Some(format!("tile={columns}x{rows}")),
+            ]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(",");
+
+            Ok(vec![format!("-vf {filters} -frames:v 1")])
+        }
+        None => Ok((0..args.count)
+            .map(|i| {
+                let time = duration * (i as f64 + 0.5) / args.count as f64;
+                match &scale {
+                    Some(scale) => format!("-ss {time:.3} -vf {scale} -frames:v 1"),
+                    None => format!("-ss {time:.3} -frames:v 1"),
+                }
+            })
+            .collect()),
+    }
+}
+
+/// Parses `COLUMNSxROWS`, e.g. `4x3`
+fn parse_grid(value: &str) -> Result<(u32, u32), String> {
+    let parsed = value.split_once(['x', 'X']).and_then(|(columns, rows)| {
+        Some((columns.trim().parse().ok()?, rows.trim().parse().ok()?))
+    });
+
+    match parsed {
+        Some((columns, rows)) if columns > 0 && rows > 0 => Ok((columns, rows)),
+        _ => Err(format!(
+            "Invalid grid '{value}', expected columns and rows like 4x3"
+        )),
+    }
+}
+
+/// Checks `--sheet` for use as a clap value parser.
+pub fn validate_grid(value: &str) -> Result<String, String> {
+    parse_grid(value).map(|_| value.to_string())
+}
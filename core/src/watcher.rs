@@ -0,0 +1,178 @@
+use crate::args::WatchArgs;
+use crate::{CmdArgs, Filter, InputFile, Logger, MediaInfo, Processor, Progress, Queue};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+#[cfg(feature = "ui")]
+use tauri::AppHandle;
+use walkdir::WalkDir;
+
+/// Watches `watch_args.dir` and hands every new file to `processor` once its size stopped
+/// changing for the configured settle time. Keeps running until ffzap is stopped.
+pub fn watch(
+    processor: &Processor,
+    cmd_args: &CmdArgs,
+    watch_args: &WatchArgs,
+    logger: Arc<Logger>,
+    progress: Arc<Progress>,
+    #[cfg(feature = "ui")] app_handle: AppHandle,
+) -> Result<(), String> {
+    if cmd_args.report.is_some() {
+        return Err(
+            "--report can't be used with watch, it only ends when ffzap is stopped.".to_string(),
+        );
+    }
+
+    let dir = match fs::canonicalize(&watch_args.dir) {
+        Ok(dir) if dir.is_dir() => dir,
+        _ => return Err(format!("{} is not a directory.", watch_args.dir.display())),
+    };
+
+    let filter = match &cmd_args.filter {
+        Some(filter) => Some(Filter::parse(filter)?),
+        None => None,
+    };
+
+    let (tx, rx) = channel();
+    let mut watcher = notify::recommended_watcher(tx)
+        .map_err(|err| format!("Could not start watching {}: {err}", dir.display()))?;
+    watcher
+        .watch(&dir, RecursiveMode::Recursive)
+        .map_err(|err| format!("Could not start watching {}: {err}", dir.display()))?;
+
+    let queue = Arc::new(Queue::new());
+    let settle_time = Duration::from_secs(watch_args.settle_time);
+    // sources are moved there after processing and must not be picked up again
+    let ignored_dir = cmd_args
+        .move_processed
+        .as_ref()
+        .map(|d| fs::canonicalize(d).unwrap_or_else(|_| d.clone()));
+    // outputs of processed files may be written to the watched directory as well
+    let written_outputs = processor.get_written_outputs();
+    let verbose = cmd_args.verbose;
+
+    let mut pending: HashMap<PathBuf, (u64, Instant)> = HashMap::new();
+    if watch_args.process_existing {
+        for entry in WalkDir::new(&dir).into_iter().flatten() {
+            if entry.file_type().is_file() {
+                pending.insert(entry.into_path(), (u64::MAX, Instant::now()));
+            }
+        }
+    }
+
+    logger.log_main_info(format!("Watching {} for new files", dir.display()), verbose);
+
+    let events_queue = Arc::clone(&queue);
+    thread::spawn(move || {
+        let is_ignored = |path: &Path| {
+            ignored_dir
+                .as_ref()
+                .is_some_and(|ignored_dir| path.starts_with(ignored_dir))
+                || written_outputs.lock().unwrap().contains(path)
+        };
+        // queued files with their modification time, so only files that were replaced after
+        // they were queued are processed again
+        let mut queued: HashMap<PathBuf, Option<SystemTime>> = HashMap::new();
+        let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+
+        loop {
+            match rx.recv_timeout(Duration::from_secs(1)) {
+                Ok(Ok(event)) => match event.kind {
+                    EventKind::Create(_) | EventKind::Modify(_) => {
+                        for path in event.paths {
+                            if path.is_dir() && !is_ignored(&path) {
+                                // files created before the new directory was watched
+                                for entry in WalkDir::new(&path).into_iter().flatten() {
+                                    if entry.file_type().is_file() {
+                                        pending
+                                            .entry(entry.into_path())
+                                            .or_insert((u64::MAX, Instant::now()));
+                                    }
+                                }
+                            } else if path.is_file()
+                                && !is_ignored(&path)
+                                && queued.get(&path) != Some(&modified(&path))
+                            {
+                                pending.insert(path, (u64::MAX, Instant::now()));
+                            } else if !path.exists() {
+                                // the old name of a renamed file
+                                queued.remove(&path);
+                            }
+                        }
+                    }
+                    EventKind::Remove(_) => {
+                        for path in event.paths {
+                            pending.remove(&path);
+                            queued.remove(&path);
+                        }
+                    }
+                    _ => {}
+                },
+                Ok(Err(err)) => logger.log_main_error(format!("Watch error: {err}"), verbose),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            let now = Instant::now();
+            pending.retain(|path, (size, since)| {
+                let current_size = match fs::metadata(path) {
+                    Ok(metadata) if metadata.is_file() => metadata.len(),
+                    _ => return false,
+                };
+
+                if current_size != *size {
+                    *size = current_size;
+                    *since = now;
+                    return true;
+                }
+
+                if now.duration_since(*since) < settle_time {
+                    return true;
+                }
+
+                // outputs are only known once processing of their source has started
+                if is_ignored(path) {
+                    return false;
+                }
+
+                if let Some(filter) = &filter {
+                    if let Err(reason) =
+                        MediaInfo::probe(path).and_then(|info| filter.evaluate(&info))
+                    {
+                        logger.log_main_info(
+                            format!("Skipping {} ({reason})", path.display()),
+                            verbose,
+                        );
+                        return false;
+                    }
+                }
+
+                logger.log_main_info(format!("Queueing {}", path.display()), verbose);
+                queued.insert(path.clone(), modified(path));
+                progress.inc_len(1);
+                events_queue.push(InputFile::new(path.clone(), Some(dir.clone())));
+                false
+            });
+        }
+
+        // the watcher is gone, let the workers finish what's left
+        events_queue.close();
+    });
+
+    processor.process_queue(
+        queue,
+        cmd_args,
+        #[cfg(feature = "ui")]
+        app_handle,
+    );
+
+    // keep the watcher alive until processing has stopped
+    drop(watcher);
+
+    Ok(())
+}
@@ -1,6 +1,6 @@
-use std::{sync::Arc, thread};
+use std::{collections::BTreeMap, sync::Arc, thread};
 
-use ffzap_core::{load_paths, CmdArgs, Logger, Processor, Progress};
+use ffzap_core::{load_paths, CmdArgs, Config, Logger, Preset, Processor, Progress};
 use tauri::{AppHandle, Emitter, WindowEvent};
 
 #[tauri::command]
@@ -8,24 +8,20 @@ fn start_job(app: AppHandle, options: String) {
     let args = serde_json::from_str::<CmdArgs>(&options).unwrap();
 
     let app_handle = app.clone();
-    let paths = load_paths(&args, &app_handle);
+    let (paths, skipped_paths) = load_paths(&args, &app_handle);
     let progress = Arc::new(Progress::new(paths.len(), args.eta));
     let logger = Arc::new(Logger::new(Arc::clone(&progress), app_handle.clone()));
     let processor = Processor::new(Arc::clone(&logger), Arc::clone(&progress));
 
+    logger.append_skipped_paths_to_log(&skipped_paths);
+    for path in skipped_paths {
+        let _ = app_handle.emit("log-update-info", format!("Skipping {path}"));
+    }
+
     let _ = app_handle.emit("update-total-file-count", paths.len());
 
     thread::spawn(move || {
-        processor.process_files(
-            paths,
-            args.thread_count,
-            args.ffmpeg_options,
-            args.output,
-            args.overwrite,
-            args.verbose,
-            args.delete,
-            app_handle.clone(),
-        );
+        processor.process_files(paths, &args, app_handle.clone());
 
         let _ = app_handle.emit(
             "job-finished",
@@ -43,7 +39,13 @@ pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_opener::init())
-        .invoke_handler(tauri::generate_handler![start_job, stop_jobs])
+        .invoke_handler(tauri::generate_handler![
+            start_job,
+            stop_jobs,
+            list_presets,
+            save_preset,
+            delete_preset
+        ])
         .on_window_event(|_window, event| {
             // Windows does not automatically tear down spawned child processes when closing the Tauri main window
             // This is relevant if the window is closed while there's still ffmpeg processes running
@@ -56,6 +58,25 @@ pub fn run() {
         .expect("error while running tauri application");
 }
 
+#[tauri::command]
+fn list_presets() -> Result<BTreeMap<String, Preset>, String> {
+    Config::load().map(|config| config.presets)
+}
+
+#[tauri::command]
+fn save_preset(name: String, preset: Preset) -> Result<(), String> {
+    let mut config = Config::load()?;
+    config.presets.insert(name, preset);
+    config.save()
+}
+
+#[tauri::command]
+fn delete_preset(name: String) -> Result<(), String> {
+    let mut config = Config::load()?;
+    config.presets.remove(&name);
+    config.save()
+}
+
 #[tauri::command]
 fn stop_jobs(app_handle: AppHandle) {
     thread::spawn(move || {
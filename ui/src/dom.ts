@@ -11,10 +11,7 @@ export function addSpacerToLog(): void {
 export function updateLog(toWrite: string, severity: LogSeverity): void {
   const logEntry: HTMLDivElement = document.createElement('div');
   logEntry.innerText = toWrite;
-  logEntry.classList.add(
-    'log-entry',
-    severity === LogSeverity.INFO ? 'info' : 'error',
-  );
+  logEntry.classList.add('log-entry', severity);
 
   document.getElementById('log-content')?.appendChild(logEntry);
 }
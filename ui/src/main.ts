@@ -1,6 +1,6 @@
 import { open } from '@tauri-apps/plugin-dialog';
 import { invoke } from '@tauri-apps/api/core';
-import { CmdArgs, LogSeverity } from './models';
+import { CmdArgs, LogSeverity, Preset } from './models';
 import { listen } from '@tauri-apps/api/event';
 import {
   addSpacerToLog,
@@ -23,6 +23,9 @@ document.addEventListener('DOMContentLoaded', () => {
   let filesList: string | null = null;
   let totalFiles: number = 0;
   let doneFiles: number = 0;
+  let presets: Record<string, Preset> = {};
+  // presets can contain a filter, which has no input of its own
+  let presetFilter: string | null = null;
 
   prepareTabs();
 
@@ -73,6 +76,10 @@ document.addEventListener('DOMContentLoaded', () => {
           name: 'File Lists',
           extensions: ['txt', 'lst', 'list'],
         },
+        {
+          name: 'Manifests',
+          extensions: ['csv', 'jsonl'],
+        },
       ],
     });
 
@@ -89,6 +96,103 @@ document.addEventListener('DOMContentLoaded', () => {
     validateButton(allFiles, filesList);
   });
 
+  const presetSelect = document.getElementById(
+    'preset-select',
+  ) as HTMLSelectElement;
+  const presetNameInput = document.getElementById(
+    'preset-name',
+  ) as HTMLInputElement;
+
+  async function loadPresets(selected: string): Promise<void> {
+    try {
+      presets = await invoke<Record<string, Preset>>('list_presets');
+    } catch (err) {
+      showLogSection();
+      updateLog(String(err), LogSeverity.ERROR);
+      return;
+    }
+
+    presetSelect.replaceChildren(new Option('No preset', ''));
+    for (const name of Object.keys(presets)) {
+      presetSelect.appendChild(new Option(name, name, false, name === selected));
+    }
+  }
+
+  presetSelect.addEventListener('change', () => {
+    const name = presetSelect.value;
+    const preset: Preset | undefined = presets[name];
+    presetNameInput.value = name;
+    presetFilter = preset?.filter ?? null;
+
+    if (!preset) {
+      return;
+    }
+
+    // the UI runs a fixed number of threads
+    if (typeof preset.thread_count === 'number') {
+      (document.getElementById('thread-count') as HTMLInputElement).value =
+        `${preset.thread_count}`;
+    }
+    if (preset.ffmpeg_options) {
+      (document.getElementById('ffmpeg-options') as HTMLTextAreaElement).value =
+        preset.ffmpeg_options;
+    }
+    if (preset.output) {
+      (document.getElementById('output-pattern') as HTMLInputElement).value =
+        preset.output;
+    }
+
+    validateButton(allFiles, filesList);
+  });
+
+  document.getElementById('save-preset-btn')!.addEventListener('click', async () => {
+    const name = presetNameInput.value.trim();
+    if (!name) {
+      return;
+    }
+
+    const ffmpegOptions = (
+      document.getElementById('ffmpeg-options') as HTMLTextAreaElement
+    ).value;
+    const preset: Preset = {
+      thread_count: Number(
+        (document.getElementById('thread-count') as HTMLInputElement).value,
+      ),
+      ffmpeg_options: ffmpegOptions ? ffmpegOptions : null,
+      output:
+        (document.getElementById('output-pattern') as HTMLInputElement).value ||
+        null,
+      filter: presetFilter,
+    };
+
+    try {
+      await invoke('save_preset', { name, preset });
+    } catch (err) {
+      showLogSection();
+      updateLog(String(err), LogSeverity.ERROR);
+    }
+    await loadPresets(name);
+  });
+
+  document.getElementById('delete-preset-btn')!.addEventListener('click', async () => {
+    const name = presetSelect.value;
+    if (!name) {
+      return;
+    }
+
+    try {
+      await invoke('delete_preset', { name });
+    } catch (err) {
+      showLogSection();
+      updateLog(String(err), LogSeverity.ERROR);
+    }
+    presetNameInput.value = '';
+    presetFilter = null;
+    await loadPresets('');
+  });
+
+  loadPresets('');
+
   document.getElementById('start-btn')!.addEventListener('click', event => {
     const startBtn: HTMLButtonElement = event.target as HTMLButtonElement;
     const threadCountInput: HTMLInputElement = document.getElementById(
@@ -103,6 +207,9 @@ document.addEventListener('DOMContentLoaded', () => {
     const overwriteCheckbox: HTMLInputElement = document.getElementById(
       'overwrite',
     )! as HTMLInputElement;
+    const skipExistingNewerCheckbox: HTMLInputElement = document.getElementById(
+      'skip-existing-newer',
+    )! as HTMLInputElement;
     const verboseCheckbox: HTMLInputElement = document.getElementById(
       'verbose',
     )! as HTMLInputElement;
@@ -116,11 +223,23 @@ document.addEventListener('DOMContentLoaded', () => {
         ? ffmpegOptionsInput.value
         : null,
       input: allFiles ? allFiles : null,
-      file_list: filesList ? filesList : null,
+      // manifests are picked like file lists, but can override options per file
+      file_list: filesList && !isManifest(filesList) ? filesList : null,
+      manifest: filesList && isManifest(filesList) ? filesList : null,
+      null: false,
+      filter: presetFilter,
       overwrite: overwriteCheckbox.checked,
+      skip_existing_newer: skipExistingNewerCheckbox.checked,
+      skip_unchanged: false,
+      two_pass: false,
       verbose: verboseCheckbox.checked,
       delete: deleteCheckbox.checked,
       eta: false,
+      quality_check: false,
+      copy_original: false,
+      preserve_times: false,
+      preserve_xattrs: false,
+      preserve_owner: false,
       output: outputPatternInput.value,
     };
 
@@ -141,6 +260,7 @@ document.addEventListener('DOMContentLoaded', () => {
     browseFilesBtn.disabled = true;
     browseListBtn.disabled = true;
     overwriteCheckbox.disabled = true;
+    skipExistingNewerCheckbox.disabled = true;
     verboseCheckbox.disabled = true;
     deleteCheckbox.disabled = true;
   });
@@ -157,6 +277,9 @@ document.addEventListener('DOMContentLoaded', () => {
     const overWriteCheckBox: HTMLInputElement = document.getElementById(
       'overwrite',
     ) as HTMLInputElement;
+    const skipExistingNewerCheckBox: HTMLInputElement = document.getElementById(
+      'skip-existing-newer',
+    ) as HTMLInputElement;
     const verboseCheckBox: HTMLInputElement = document.getElementById(
       'verbose',
     ) as HTMLInputElement;
@@ -168,6 +291,7 @@ document.addEventListener('DOMContentLoaded', () => {
     browseFilesBtn.disabled = false;
     browseListBtn.disabled = false;
     overWriteCheckBox.disabled = false;
+    skipExistingNewerCheckBox.disabled = false;
     verboseCheckBox.disabled = false;
     deleteSourceCheckBox.disabled = false;
 
@@ -214,6 +338,10 @@ document.addEventListener('DOMContentLoaded', () => {
     updateLog(event.payload, LogSeverity.INFO);
   });
 
+  listen<string>('log-update-warning', event => {
+    updateLog(event.payload, LogSeverity.WARNING);
+  });
+
   listen<string>('log-update-error', event => {
     updateLog(event.payload, LogSeverity.ERROR);
   });
@@ -222,3 +350,7 @@ document.addEventListener('DOMContentLoaded', () => {
     updateLog(event.payload, LogSeverity.ERROR);
   });
 });
+
+function isManifest(path: string): boolean {
+  return /\.(csv|jsonl)$/i.test(path);
+}
@@ -1,17 +1,54 @@
 // For more information on what these properties do, see shared/src/args.rs
 export interface CmdArgs {
-  thread_count: number;
+  preset?: string | null;
+  thread_count: number | 'auto';
+  min_threads?: number | null;
+  max_threads?: number | null;
   ffmpeg_options?: string | null;
   input?: string[] | null;
   file_list?: string | null;
+  manifest?: string | null;
+  base_dir?: string | null;
+  null: boolean;
+  filter?: string | null;
   overwrite: boolean;
+  skip_existing_newer: boolean;
+  skip_unchanged: boolean;
   verbose: boolean;
   delete: boolean;
+  skip_if?: string | null;
+  remux_if?: string | null;
+  report?: string | null;
+  move_processed?: string | null;
+  ext_map?: string | null;
+  two_pass: boolean;
+  target_size?: number | null;
+  loudnorm?: string | null;
+  sanitize?: 'ntfs' | 'fat' | 'posix' | null;
   eta: boolean;
   output: string;
+  extra_output?: string[];
+  quality_check: boolean;
+  min_ssim?: number | null;
+  min_psnr?: number | null;
+  quality_fallback?: string | null;
+  keep_smaller?: number | null;
+  copy_original: boolean;
+  preserve_times: boolean;
+  preserve_xattrs: boolean;
+  preserve_owner: boolean;
+  segment_length?: number | null;
+}
+
+export interface Preset {
+  ffmpeg_options?: string | null;
+  output?: string | null;
+  thread_count?: number | 'auto' | null;
+  filter?: string | null;
 }
 
 export enum LogSeverity {
   ERROR = 'error',
+  WARNING = 'warning',
   INFO = 'info',
 }